@@ -0,0 +1,428 @@
+use std::ffi::{OsStr, OsString};
+use std::fs;
+use std::path::Path;
+
+use crate::build_state::{self, InputHasher};
+use crate::error::{self, AutoTeXErr};
+use crate::tex_source::{self, read_if_exists};
+use crate::texfile_info::TeXFileInfo;
+
+// Programs that make a bibliography from the aux (or bcf) file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BibBackend {
+    Bibtex,
+    Biber,
+}
+
+impl BibBackend {
+    // Parse the name of a backend given by config or command line as `key`.
+    // "auto" means that the backend is detected from the document.
+    pub fn from_name(name: &str, key: &str) -> error::Result<Option<Self>> {
+        match name.to_lowercase().as_str() {
+            "auto" => Ok(None),
+            "bibtex" => Ok(Some(Self::Bibtex)),
+            "biber" => Ok(Some(Self::Biber)),
+            _ => Err(AutoTeXErr::invalid_value(
+                key,
+                name,
+                "one of auto, bibtex or biber",
+            )),
+        }
+    }
+
+    pub fn program(&self) -> &'static str {
+        match self {
+            Self::Bibtex => "bibtex",
+            Self::Biber => "biber",
+        }
+    }
+}
+
+// Lines of an aux file that bibtex reads
+const BIBTEX_AUX_ENTRIES: [&str; 3] = ["\\citation", "\\bibdata", "\\bibstyle"];
+
+// Limit of nested \@input{...} entries in aux files
+const MAX_AUX_DEPTH: usize = 16;
+
+// Append `ext` to the name, keeping dots of a job name like `paper.v2`
+pub fn with_ext(mainfile: &OsStr, ext: &str) -> OsString {
+    let mut name = mainfile.to_os_string();
+    name.push(ext);
+    name
+}
+
+// Find the backend written by biblatex as \abx@aux@backend{...} in the aux file
+fn backend_in_aux(aux: &str) -> Option<BibBackend> {
+    tex_source::find_commands(aux, "abx@aux@backend")
+        .iter()
+        .find_map(|cmd| {
+            BibBackend::from_name(&cmd.argument, "\\abx@aux@backend")
+                .ok()
+                .flatten()
+        })
+}
+
+// Detect the backend of biblatex from the aux file. biblatex writes
+// `\abx@aux@...` entries with any backend, and `\bibstyle{biblatex}` only for bibtex.
+fn biblatex_backend(aux: &str) -> Option<BibBackend> {
+    if let Some(backend) = backend_in_aux(aux) {
+        return Some(backend);
+    }
+    if !aux.contains("\\abx@aux@") {
+        return None;
+    }
+    let for_bibtex = tex_source::find_commands(aux, "bibstyle")
+        .iter()
+        .any(|cmd| cmd.argument.trim() == "biblatex");
+    Some(if for_bibtex {
+        BibBackend::Bibtex
+    } else {
+        BibBackend::Biber
+    })
+}
+
+// A bibliography program and the job it runs on
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BibJob {
+    pub backend: BibBackend,
+    pub target: OsString,
+    // Aux files of \include'd files whose citations bibtex reads through
+    // \@input{...} for this job
+    pub included_aux: Vec<OsString>,
+}
+
+impl BibJob {
+    // Key of this job in the build state
+    pub fn state_key(&self) -> String {
+        format!(
+            "{}:{}",
+            self.backend.program(),
+            self.target.to_string_lossy()
+        )
+    }
+
+    // The bbl file must exist, otherwise the job runs regardless of its inputs
+    pub fn output_exists(&self, dir: &Path) -> bool {
+        dir.join(with_ext(&self.target, ".bbl")).exists()
+    }
+
+    // Hash the inputs of this job: the bibliography lines of the aux files
+    // (or the whole bcf file for biber) and the local database files.
+    // `dir` is the directory of the main file.
+    pub fn input_hash(&self, dir: &Path) -> error::Result<u64> {
+        let mut hasher = InputHasher::new();
+        let databases = match self.backend {
+            BibBackend::Bibtex => {
+                let aux =
+                    read_if_exists(dir.join(with_ext(&self.target, ".aux")))?.unwrap_or_default();
+                let mut entries = aux.clone();
+                for included in self.included_aux.iter() {
+                    entries.push_str(&read_if_exists(dir.join(included))?.unwrap_or_default());
+                }
+                for line in entries
+                    .lines()
+                    .filter(|line| BIBTEX_AUX_ENTRIES.iter().any(|entry| line.contains(entry)))
+                {
+                    hasher.write(line.as_bytes());
+                }
+                tex_source::find_commands(&aux, "bibdata")
+                    .iter()
+                    .flat_map(|cmd| {
+                        tex_source::split_list(&cmd.argument)
+                            .map(|name| format!("{}.bib", name.trim_end_matches(".bib")))
+                            .collect::<Vec<_>>()
+                    })
+                    .collect::<Vec<_>>()
+            }
+            BibBackend::Biber => {
+                let bcf =
+                    read_if_exists(dir.join(with_ext(&self.target, ".bcf")))?.unwrap_or_default();
+                hasher.write(bcf.as_bytes());
+                bcf.split("<bcf:datasource")
+                    .skip(1)
+                    .filter_map(|rest| rest.split_once('>'))
+                    .filter_map(|(_, rest)| rest.split_once("</bcf:datasource>"))
+                    .map(|(name, _)| name.trim().to_string())
+                    .collect()
+            }
+        };
+        for database in databases {
+            hasher.write(database.as_bytes());
+            hasher.write_file(dir.join(&database))?;
+        }
+        Ok(hasher.finish())
+    }
+}
+
+// Take the aux files of \include'd files, written as \@input{...} entries
+// in the main aux file and in those files, with their contents.
+// Their names are relative to `dir`, the directory of the main file.
+fn input_aux_files(dir: &Path, main_aux: &str) -> error::Result<Vec<(OsString, String)>> {
+    let mut output: Vec<(OsString, String)> = vec![];
+    let mut pending = vec![(main_aux.to_string(), 0usize)];
+    while let Some((contents, depth)) = pending.pop() {
+        for cmd in tex_source::find_commands(&contents, "@input") {
+            let name = OsString::from(&cmd.argument);
+            if depth < MAX_AUX_DEPTH && !output.iter().any(|(known, _)| *known == name) {
+                let child = read_if_exists(dir.join(&name))?.unwrap_or_default();
+                pending.push((child.clone(), depth + 1));
+                output.push((name, child));
+            }
+        }
+    }
+    Ok(output)
+}
+
+// Collect the aux files of the document other than the main one: the ones of
+// \include'd files, the ones of bibunits (bu1.aux, ...) and the ones of
+// multibib declared by \newcites{...} in the main file.
+fn collect_aux_files(
+    tex_info: &TeXFileInfo,
+    included: &[(OsString, String)],
+) -> error::Result<Vec<OsString>> {
+    let mut output: Vec<OsString> = included.iter().map(|(name, _)| name.clone()).collect();
+
+    let read_error = |err| AutoTeXErr::file(&tex_info.current_dir, err);
+    for entry in fs::read_dir(&tex_info.current_dir).map_err(read_error)? {
+        let name = entry.map_err(read_error)?.file_name();
+        let is_bibunit = name
+            .to_str()
+            .and_then(|name| name.strip_prefix("bu"))
+            .and_then(|name| name.strip_suffix(".aux"))
+            .is_some_and(|num| !num.is_empty() && num.chars().all(|ch| ch.is_ascii_digit()));
+        if is_bibunit && !output.contains(&name) {
+            output.push(name);
+        }
+    }
+
+    let main_source =
+        read_if_exists(tex_info.in_dir(tex_info.get_main_tex_file()))?.unwrap_or_default();
+    let main_source = tex_source::strip_comments(&main_source);
+    for cmd in tex_source::find_commands(&main_source, "newcites") {
+        for suffix in tex_source::split_list(&cmd.argument) {
+            let name = OsString::from(format!("{}.aux", suffix));
+            if !output.contains(&name) {
+                output.push(name);
+            }
+        }
+    }
+
+    Ok(output)
+}
+
+fn has_entry(aux: &str, name: &str) -> bool {
+    !tex_source::find_commands(aux, name).is_empty()
+}
+
+// An aux file needs bibtex if it declares a database, a style and
+// at least one citation is in it or in the aux files which it reads.
+// Otherwise bibtex fails or does nothing.
+fn needs_bibtex(aux: &str, included: &[&str]) -> bool {
+    has_entry(aux, "bibdata")
+        && has_entry(aux, "bibstyle")
+        && std::iter::once(aux)
+            .chain(included.iter().copied())
+            .any(|aux| has_entry(aux, "citation"))
+}
+
+// Decide which bibliography programs must run after the last pass
+pub fn take_jobs(
+    tex_info: &TeXFileInfo,
+    overridden: Option<BibBackend>,
+) -> error::Result<Vec<BibJob>> {
+    let main_aux_path = tex_info.in_dir(with_ext(&tex_info.jobname, ".aux"));
+    let bcf_path = tex_info.in_dir(with_ext(&tex_info.jobname, ".bcf"));
+    let main_aux = match read_if_exists(&main_aux_path)? {
+        Some(aux) => aux,
+        None => return Ok(vec![]),
+    };
+
+    // biber reads every refsection from the single bcf file of the main job
+    let biber_detected = match biblatex_backend(&main_aux) {
+        Some(backend) => backend == BibBackend::Biber,
+        // biblatex writes a bcf file only if biber is its backend
+        None => build_state::written_in_last_pass(&bcf_path, &main_aux_path),
+    };
+    if biber_detected && overridden != Some(BibBackend::Bibtex) {
+        return Ok(vec![BibJob {
+            backend: BibBackend::Biber,
+            target: tex_info.jobname.clone(),
+            included_aux: vec![],
+        }]);
+    }
+
+    // With a plain \include, citations are written only to the aux files of
+    // the included files, which bibtex reads for the main job through \@input.
+    // Those with their own \bibdata (chapterbib) make jobs of their own.
+    let included = input_aux_files(&tex_info.current_dir, &main_aux)?;
+    let (included_aux, included_sources): (Vec<OsString>, Vec<&str>) = included
+        .iter()
+        .filter(|(_, aux)| !has_entry(aux, "bibdata"))
+        .map(|(name, aux)| (name.clone(), aux.as_str()))
+        .unzip();
+
+    let mut output = vec![];
+    if needs_bibtex(&main_aux, &included_sources) {
+        output.push(BibJob {
+            backend: BibBackend::Bibtex,
+            target: tex_info.jobname.clone(),
+            included_aux,
+        });
+    }
+    for aux_file in collect_aux_files(tex_info, &included)? {
+        if read_if_exists(tex_info.in_dir(&aux_file))?.is_some_and(|aux| needs_bibtex(&aux, &[])) {
+            let target = Path::new(&aux_file).with_extension("").into_os_string();
+            output.push(BibJob {
+                backend: BibBackend::Bibtex,
+                target,
+                included_aux: vec![],
+            });
+        }
+    }
+
+    // biber always works on the main job
+    if overridden == Some(BibBackend::Biber) && !output.is_empty() {
+        output = vec![BibJob {
+            backend: BibBackend::Biber,
+            target: tex_info.jobname.clone(),
+            included_aux: vec![],
+        }];
+    }
+
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::magic_comments::MagicComments;
+
+    // A document `main` in a directory of its own with the given files
+    fn document(name: &str, files: &[(&str, &str)]) -> TeXFileInfo {
+        let dir = std::env::temp_dir().join(format!("autotex-bib-{}-{}", std::process::id(), name));
+        fs::create_dir_all(&dir).unwrap();
+        for (file, contents) in files {
+            fs::write(dir.join(file), contents).unwrap();
+        }
+        TeXFileInfo {
+            filenames: vec![],
+            mainfile: "main".into(),
+            jobname: "main".into(),
+            current_dir: dir,
+            magic_comments: MagicComments::default(),
+            asymptote_exists: false,
+        }
+    }
+
+    fn targets(jobs: &[BibJob]) -> Vec<(BibBackend, &str)> {
+        jobs.iter()
+            .map(|job| (job.backend, job.target.to_str().unwrap()))
+            .collect()
+    }
+
+    fn remove(tex_info: TeXFileInfo) {
+        fs::remove_dir_all(tex_info.current_dir).unwrap();
+    }
+
+    const BIBDATA: &str = "\\bibstyle{plain}\n\\bibdata{refs}\n";
+
+    #[test]
+    fn citations_of_nested_input_aux() {
+        let main_aux = format!("\\relax\n\\@input{{chap1.aux}}\n{}", BIBDATA);
+        let tex_info = document(
+            "input",
+            &[
+                ("main.aux", &main_aux),
+                ("chap1.aux", "\\relax\n\\@input{sec.aux}\n"),
+                ("sec.aux", "\\citation{knuth}\n"),
+            ],
+        );
+
+        let jobs = take_jobs(&tex_info, None).unwrap();
+        assert_eq!(targets(&jobs), vec![(BibBackend::Bibtex, "main")]);
+        let included: Vec<&OsString> = jobs[0].included_aux.iter().collect();
+        assert_eq!(included, vec!["chap1.aux", "sec.aux"]);
+        remove(tex_info);
+    }
+
+    #[test]
+    fn input_aux_which_reads_itself() {
+        let main_aux = format!("\\@input{{loop.aux}}\n{}", BIBDATA);
+        let tex_info = document(
+            "loop",
+            &[
+                ("main.aux", &main_aux),
+                ("loop.aux", "\\@input{loop.aux}\n"),
+            ],
+        );
+
+        // No citation anywhere, so bibtex has nothing to do
+        assert!(take_jobs(&tex_info, None).unwrap().is_empty());
+        remove(tex_info);
+    }
+
+    #[test]
+    fn included_aux_with_its_own_bibdata() {
+        let chapter = format!("\\citation{{lamport}}\n{}", BIBDATA);
+        let tex_info = document(
+            "chapterbib",
+            &[("main.aux", "\\@input{chap.aux}\n"), ("chap.aux", &chapter)],
+        );
+
+        let jobs = take_jobs(&tex_info, None).unwrap();
+        assert_eq!(targets(&jobs), vec![(BibBackend::Bibtex, "chap")]);
+        remove(tex_info);
+    }
+
+    #[test]
+    fn bibunits_and_newcites() {
+        let unit = format!("\\citation{{knuth}}\n{}", BIBDATA);
+        let tex_info = document(
+            "units",
+            &[
+                (
+                    "main.tex",
+                    "\\newcites{sec}{Secondary}\n% \\newcites{old}{Old}\n",
+                ),
+                ("main.aux", "\\relax\n"),
+                ("bu1.aux", &unit),
+                ("bu2.aux", BIBDATA),
+                ("bux.aux", &unit),
+                ("sec.aux", &unit),
+                ("old.aux", &unit),
+            ],
+        );
+
+        let mut jobs = targets(&take_jobs(&tex_info, None).unwrap())
+            .into_iter()
+            .map(|(_, target)| target.to_string())
+            .collect::<Vec<_>>();
+        jobs.sort();
+        // bu2 has no citation, `bux` is not a bibunit and `old` is commented out
+        assert_eq!(jobs, vec!["bu1", "sec"]);
+        remove(tex_info);
+    }
+
+    #[test]
+    fn biblatex_entries_choose_the_backend() {
+        let tex_info = document(
+            "biblatex",
+            &[(
+                "main.aux",
+                "\\abx@aux@refcontext{nty/global//global/global}\n",
+            )],
+        );
+        let jobs = take_jobs(&tex_info, None).unwrap();
+        assert_eq!(targets(&jobs), vec![(BibBackend::Biber, "main")]);
+
+        // bibtex needs \bibdata, which biblatex writes only for its bibtex backend
+        let jobs = take_jobs(&tex_info, Some(BibBackend::Bibtex)).unwrap();
+        assert!(jobs.is_empty());
+        remove(tex_info);
+    }
+
+    #[test]
+    fn job_paths_keep_dotted_names() {
+        assert_eq!(with_ext(OsStr::new("paper.v2"), ".aux"), "paper.v2.aux");
+    }
+}
@@ -0,0 +1,139 @@
+use std::collections::BTreeMap;
+use std::ffi::OsStr;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::error::{self, AutoTeXErr};
+
+// Directory where the hashes are stored, relative to the main file
+pub const STATE_DIR: &str = ".autotex-cache";
+
+// FNV-1a constants
+const FNV_OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+// Files written at the end of the same pass may differ by this much
+const SAME_PASS_TOLERANCE: Duration = Duration::from_secs(10);
+
+// Check whether a file was written by the same pass as the aux file.
+// A file much older than the aux file is a leftover of a previous build.
+pub fn written_in_last_pass(path: &Path, aux: &Path) -> bool {
+    let modified = |path: &Path| path.metadata().and_then(|meta| meta.modified());
+    match (modified(path), modified(aux)) {
+        (Ok(time), Ok(aux_time)) => time + SAME_PASS_TOLERANCE >= aux_time,
+        (Ok(_), Err(_)) => true,
+        _ => false,
+    }
+}
+
+// Hash inputs of a tool. This must not change between versions of Rust,
+// so that the stored hashes are still valid after rebuilding autotex.
+#[derive(Debug, Clone, Copy)]
+pub struct InputHasher(u64);
+
+impl InputHasher {
+    pub fn new() -> Self {
+        Self(FNV_OFFSET)
+    }
+
+    pub fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 ^= u64::from(*byte);
+            self.0 = self.0.wrapping_mul(FNV_PRIME);
+        }
+        // Separate consecutive inputs so that "ab" + "c" differs from "a" + "bc"
+        self.0 ^= 0xff;
+        self.0 = self.0.wrapping_mul(FNV_PRIME);
+    }
+
+    // Hash the contents of a file. A missing file is hashed as an empty one.
+    pub fn write_file<P: AsRef<Path>>(&mut self, path: P) -> error::Result<()> {
+        match fs::read(path.as_ref()) {
+            Ok(bytes) => self.write(&bytes),
+            Err(err) if err.kind() == ErrorKind::NotFound => self.write(&[]),
+            Err(err) => return Err(AutoTeXErr::file(path.as_ref(), err)),
+        }
+        Ok(())
+    }
+
+    pub fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+impl Default for InputHasher {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Hashes of the inputs of each auxiliary tool when it ran successfully last time
+#[derive(Debug)]
+pub struct BuildState {
+    path: PathBuf,
+    hashes: BTreeMap<String, u64>,
+    changed: bool,
+}
+
+impl BuildState {
+    // Load the state of the given job in the directory of the main file.
+    // A missing or broken state file means an empty state.
+    pub fn load(dir: &Path, mainfile: &OsStr) -> error::Result<Self> {
+        // Append the extension so that a job like `paper.v2` keeps its own file
+        let mut name = mainfile.to_os_string();
+        name.push(".hashes");
+        let path = dir.join(STATE_DIR).join(name);
+
+        let contents = match fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(err) if err.kind() == ErrorKind::NotFound => String::new(),
+            Err(err) if err.kind() == ErrorKind::InvalidData => String::new(),
+            Err(err) => return Err(AutoTeXErr::file(&path, err)),
+        };
+        let hashes = contents
+            .lines()
+            .filter_map(|line| line.split_once('\t'))
+            .filter_map(|(hash, key)| {
+                u64::from_str_radix(hash, 16)
+                    .ok()
+                    .map(|hash| (key.to_string(), hash))
+            })
+            .collect();
+
+        Ok(Self {
+            path,
+            hashes,
+            changed: false,
+        })
+    }
+
+    pub fn is_unchanged(&self, key: &str, hash: u64) -> bool {
+        self.hashes.get(key) == Some(&hash)
+    }
+
+    pub fn record(&mut self, key: &str, hash: u64) {
+        if self.hashes.insert(key.to_string(), hash) != Some(hash) {
+            self.changed = true;
+        }
+    }
+
+    // Write the state only if a tool recorded a new hash, so that
+    // a document without tools gets no cache directory
+    pub fn save(&self) -> error::Result<()> {
+        if !self.changed || self.hashes.is_empty() {
+            return Ok(());
+        }
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir).map_err(|err| AutoTeXErr::file(dir, err))?;
+        }
+        let contents: String = self
+            .hashes
+            .iter()
+            .map(|(key, hash)| format!("{:016x}\t{}\n", hash, key))
+            .collect();
+        fs::write(&self.path, contents).map_err(|err| AutoTeXErr::file(&self.path, err))?;
+        Ok(())
+    }
+}
@@ -1,14 +1,42 @@
+use crate::bibliography::BibBackend;
+use crate::config::Config;
+use crate::engines::EngineFlags;
 use crate::error::{self, AutoTeXErr};
+use crate::export::DiagnosticsFormat;
+use crate::index::IndexSettings;
+use crate::magic_comments::MagicComments;
+use crate::packages::Installer;
+use crate::pipeline::{self, PipelineConfig};
+use crate::preamble;
+use crate::profile::Profile;
+use crate::report::ReportFormat;
+use crate::texfile_info::TeXFileInfo;
+use crate::tools::ToolPaths;
 use clap::{Arg, ArgAction, Command};
+use std::env;
 use std::ffi::OsString;
-use std::fs;
-use std::path::PathBuf;
-use yaml_rust::YamlLoader;
+use std::path::{Path, PathBuf};
 
 // Default TeX Engine and its options
-pub const TEX_ENGINES: [&str; 5] = ["pdftex", "xetex", "luatex", "tex", "plaintex"];
+pub const TEX_ENGINES: [&str; 6] = ["pdftex", "xetex", "luatex", "tex", "plaintex", "context"];
 pub const LATEX_ENGINES: [&str; 5] = ["pdflatex", "xelatex", "lualatex", "latex", "plainlatex"];
 
+pub fn is_engine(name: &str) -> bool {
+    TEX_ENGINES.contains(&name) || LATEX_ENGINES.contains(&name)
+}
+
+// The engine which the magic comments declare, and which of them declares it
+pub fn declared_engine(magic: &MagicComments) -> Option<(String, &'static str)> {
+    match magic.program().map(str::to_lowercase) {
+        Some(name) if is_engine(&name) => Some((name, "% !TEX program")),
+        _ => magic
+            .arara()
+            .iter()
+            .find(|rule| is_engine(rule))
+            .map(|rule| (rule.clone(), "% arara")),
+    }
+}
+
 // Make macros to define options and variables easily
 // These macros use only defining tex engine related options and variables in here
 macro_rules! define_tex_engine_var {
@@ -31,34 +59,51 @@ macro_rules! define_tex_engine_option {
     };
 }
 
-// Read a config file and return the position of the given engine
-// The config file must in at .config/autotex directory
-// and its name is config.yaml
-fn read_config() -> error::Result<String> {
-    let mut dir = dirs::config_dir().unwrap();
-    dir.push("autotex/config.yaml");
-    let contents = fs::read_to_string(dir).unwrap_or_default();
-    let docs = YamlLoader::load_from_str(&contents)?;
-    let doc = docs.get(0);
-    let main_engine = if let Some(d) = doc {
-        if d["engine"]["main"].is_badvalue() {
-            "pdftex"
-        } else {
-            d["engine"]["main"].as_str().unwrap()
-        }
-    } else {
-        "pdftex"
-    };
-    Ok(main_engine.to_lowercase())
+// Commands which do something else than compiling a file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SubCommand {
+    Doctor,
+    // `autotex config show`
+    ShowConfig,
+}
+
+// Where the engine was chosen.
+// Magic comments of the document win only over the config file,
+// and the engine is inferred from the document if nothing chose it.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum EngineSource {
+    #[default]
+    Default,
+    Config,
+    Profile,
+    CommandLine,
+    MagicComment,
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Default, PartialEq)]
 pub struct AutoTeXCommand {
+    pub subcommand: Option<SubCommand>,
     pub file_path: PathBuf,
     pub tex_engine: String,
+    pub engine_source: EngineSource,
     pub is_conti_compile: bool,
     pub is_view: bool,
     pub is_remove_aux: bool,
+    pub max_runs: usize,
+    pub bib_backend: Option<BibBackend>,
+    pub index_settings: IndexSettings,
+    pub pipeline: PipelineConfig,
+    pub engine_flags: EngineFlags,
+    pub report: Option<ReportFormat>,
+    pub diagnostics: Option<DiagnosticsFormat>,
+    pub diagnostics_output: Option<PathBuf>,
+    pub tools: ToolPaths,
+    pub installer: Option<Installer>,
+    pub pdf_viewer: String,
+    // The profile chosen by `--profile`
+    pub profile: Option<Profile>,
+    // Unknown keys of the config file
+    pub config_warnings: Vec<String>,
 }
 
 impl AutoTeXCommand {
@@ -71,15 +116,182 @@ impl AutoTeXCommand {
         I: Iterator<Item = T>,
         T: Into<OsString> + Clone,
     {
-        let default = read_config()?;
-        let default_engine = if TEX_ENGINES.contains(&default.as_str())
-            || LATEX_ENGINES.contains(&default.as_str())
+        let matches = Self::app().get_matches_from(args);
+
+        // These read the config file by themselves to show what is in it
+        let subcommand = match matches.subcommand() {
+            Some(("doctor", _)) => Some(SubCommand::Doctor),
+            Some(("config", _)) => Some(SubCommand::ShowConfig),
+            _ => None,
+        };
+        if subcommand.is_some() {
+            return Ok(Self {
+                subcommand,
+                ..Self::default()
+            });
+        }
+
+        // The config of a project is searched from the directory of the input file,
+        // and it can give the main file if there is no input
+        let current_dir = error::current_dir()?;
+        let input = matches.get_one::<String>("INPUT").map(PathBuf::from);
+        let start = match &input {
+            Some(path) => current_dir.join(path).parent().map(Path::to_path_buf),
+            None => None,
+        };
+        let mut defaults = Config::load(start.as_ref().unwrap_or(&current_dir))?;
+        let file_path = match (input, defaults.main_file.take()) {
+            (Some(path), _) => path,
+            (None, Some(main)) => match main.strip_prefix(&current_dir) {
+                Ok(relative) => relative.to_path_buf(),
+                Err(_) => main,
+            },
+            (None, None) => return Err(AutoTeXErr::NoFilenameInputErr(current_dir)),
+        };
+        let profile = match matches.get_one::<String>("profile") {
+            Some(name) => Some(defaults.profile(name)?),
+            None => None,
+        };
+
+        define_tex_engine_var!(use_pdftex := matches, "pdftex", "pdf");
+        define_tex_engine_var!(use_xetex := matches, "xetex", "xe");
+        define_tex_engine_var!(use_luatex := matches, "luatex", "lua");
+        define_tex_engine_var!(use_latex := matches, "latex", "la");
+
+        let (tex_engine, engine_source) = if let Some(engine) = matches.get_one::<String>("ENGINE")
         {
-            default
+            (engine.to_lowercase(), EngineSource::CommandLine)
         } else {
-            return Err(AutoTeXErr::InvalidOptionErr);
+            let engine = use_pdftex.to_string() + use_xetex + use_luatex + use_latex + "tex";
+            if !matches.get_flag("tex") && &engine == "tex" {
+                match profile.as_ref().and_then(|profile| profile.engine.clone()) {
+                    Some(engine) => (engine, EngineSource::Profile),
+                    None => match defaults.engine {
+                        Some(engine) => (engine, EngineSource::Config),
+                        None => ("pdftex".to_string(), EngineSource::Default),
+                    },
+                }
+            } else {
+                (engine, EngineSource::CommandLine)
+            }
+        };
+        let is_conti_compile = matches.get_flag("autoCompile");
+        let is_view = matches.get_flag("view");
+        let is_remove_aux = matches.get_flag("removeAuxiliary");
+        let profile_flags = profile
+            .as_ref()
+            .map(|profile| profile.flags.clone())
+            .unwrap_or_default();
+        let max_runs = match matches.get_one::<u64>("maxRuns") {
+            Some(&n) => n as usize,
+            None => profile
+                .as_ref()
+                .and_then(|profile| profile.max_runs)
+                .unwrap_or(defaults.max_runs),
+        };
+        let report = match matches.get_one::<String>("report") {
+            Some(name) => Some(ReportFormat::from_name(name)?),
+            None => None,
         };
+        let diagnostics = match matches.get_one::<String>("diagnostics") {
+            Some(name) => Some(DiagnosticsFormat::from_name(name)?),
+            None => None,
+        };
+        let diagnostics_output = matches
+            .get_one::<String>("diagnosticsOutput")
+            .map(PathBuf::from);
+        // Otherwise the report and the diagnostics are mixed in stdout
+        if report.is_some() && diagnostics.is_some() && diagnostics_output.is_none() {
+            return Err(AutoTeXErr::UsageErr(
+                "--diagnostics-output is required when --report and --diagnostics are both given"
+                    .to_string(),
+            ));
+        }
+        let bib_backend = match matches.get_one::<String>("bibBackend") {
+            Some(name) => BibBackend::from_name(name, "--bib-backend")?,
+            None => defaults.bib_backend,
+        };
+
+        Ok(Self {
+            subcommand: None,
+            file_path,
+            tex_engine,
+            engine_source,
+            is_conti_compile,
+            is_view,
+            is_remove_aux,
+            max_runs,
+            bib_backend,
+            index_settings: defaults.index_settings,
+            pipeline: defaults.pipeline,
+            engine_flags: EngineFlags {
+                cli: matches
+                    .get_many::<String>("flag")
+                    .map(|flags| flags.cloned().collect())
+                    .unwrap_or_default(),
+                profile: profile_flags,
+                ..defaults.engine_flags
+            },
+            report,
+            diagnostics,
+            diagnostics_output,
+            tools: defaults.tools,
+            installer: defaults.installer,
+            pdf_viewer: defaults.pdf_viewer,
+            profile,
+            config_warnings: defaults.warnings,
+        })
+    }
+
+    // Take the engine and the steps which magic comments of the document declare.
+    // The engine of the command line or of the profile wins over them,
+    // and so do the steps of the profile.
+    pub fn apply_magic_comments(&mut self, tex_info: &TeXFileInfo) {
+        let magic = &tex_info.magic_comments;
+        let arara = magic.arara();
+
+        if matches!(
+            self.engine_source,
+            EngineSource::Default | EngineSource::Config
+        ) {
+            if let Some((name, source)) = declared_engine(magic) {
+                say!("Compile with {} ({})", name, source);
+                self.tex_engine = name;
+                self.engine_source = EngineSource::MagicComment;
+            } else if let Some(name) = magic.program() {
+                say!(
+                    "Ignore `% !TEX program = {}`, which is not a TeX engine",
+                    name
+                );
+            }
+        }
+
+        let profile_steps = self
+            .profile
+            .as_ref()
+            .is_some_and(|profile| profile.steps.is_some());
+        if !arara.is_empty() && !profile_steps {
+            self.pipeline.steps = pipeline::arara_steps(arara, &self.pipeline);
+            say!("Steps from % arara: {}", self.pipeline.steps.join(", "));
+        }
+    }
 
+    // Choose the engine from the document if nothing else chose it
+    pub fn infer_engine(&mut self, tex_info: &TeXFileInfo) -> error::Result<()> {
+        if self.engine_source != EngineSource::Default {
+            return Ok(());
+        }
+        let inference = preamble::infer_engine(tex_info)?;
+        say!(
+            "Compile with {} since {} (set `engine.main` or use -e to choose another)",
+            inference.engine,
+            inference.reason
+        );
+        self.tex_engine = inference.engine.to_string();
+        Ok(())
+    }
+
+    fn app() -> Command {
         // Basic app information
         let app = Command::new("autotex")
             .version(env!("CARGO_PKG_VERSION"))
@@ -107,11 +319,65 @@ impl AutoTeXCommand {
             .action(ArgAction::SetTrue)
             .help("Compile TeX automatically");
 
+        // Maximum number of engine passes
+        let max_runs_option = Arg::new("maxRuns")
+            .long("max-runs")
+            .action(ArgAction::Set)
+            .value_parser(clap::value_parser!(u64).range(1..))
+            .num_args(1)
+            .help("Maximum number of engine passes until cross-references are stable");
+
+        // Override the bibliography backend
+        let bib_backend_option = Arg::new("bibBackend")
+            .long("bib-backend")
+            .action(ArgAction::Set)
+            .value_parser(["auto", "bibtex", "biber"])
+            .num_args(1)
+            .help("Declare the bibliography backend (auto, bibtex or biber)");
+
+        // Options passed to the engine
+        let flag_option = Arg::new("flag")
+            .long("flag")
+            .short('f')
+            .action(ArgAction::Append)
+            .allow_hyphen_values(true)
+            .num_args(1)
+            .help("Pass an option to the engine, like --flag=-shell-escape");
+
+        // Choose a profile of the config
+        let profile_option = Arg::new("profile")
+            .long("profile")
+            .action(ArgAction::Set)
+            .num_args(1)
+            .help("Build with a profile of the config, like draft or final");
+
+        // Machine readable report of each build
+        let report_option = Arg::new("report")
+            .long("report")
+            .action(ArgAction::Set)
+            .value_parser(["json"])
+            .num_args(1)
+            .help("Write a report of each build to stdout (json)");
+
+        // Diagnostics for editors and CI
+        let diagnostics_option = Arg::new("diagnostics")
+            .long("diagnostics")
+            .action(ArgAction::Set)
+            .value_parser(["quickfix", "gcc", "sarif", "github"])
+            .num_args(1)
+            .help("Write errors and warnings after each build (quickfix, gcc, sarif or github)");
+
+        let diagnostics_output_option = Arg::new("diagnosticsOutput")
+            .long("diagnostics-output")
+            .action(ArgAction::Set)
+            .requires("diagnostics")
+            .num_args(1)
+            .help("Write diagnostics to this file instead of stdout");
+
         // Take filepath
         let input_filepath = Arg::new("INPUT")
-            .required(true)
             .index(1)
-            .help("Sets the input filename or filepath to use");
+            .help("Sets the input filename or filepath to use, or `main` of the project config");
 
         // Declare which engines to compile
         let engine_option = Arg::new("ENGINE")
@@ -138,12 +404,29 @@ impl AutoTeXCommand {
             "Compile with latex"
         );
 
-        // Extract the matches
-        let matches = app
+        // Check the environment instead of compiling
+        let doctor_command =
+            Command::new("doctor").about("Check TeX tools, the config file and the pdf viewer");
+
+        // Show the settings read from the config file
+        let config_command = Command::new("config")
+            .about("Show the configuration")
+            .subcommand_required(true)
+            .subcommand(Command::new("show").about("Print the effective configuration as yaml"));
+
+        app.subcommand(doctor_command)
+            .subcommand(config_command)
             .args(&[
                 view_option,
                 auto_compile,
                 remove_auxiliary,
+                max_runs_option,
+                bib_backend_option,
+                flag_option,
+                profile_option,
+                report_option,
+                diagnostics_option,
+                diagnostics_output_option,
                 input_filepath,
                 engine_option,
                 pdftex,
@@ -152,34 +435,5 @@ impl AutoTeXCommand {
                 tex,
                 latex,
             ])
-            .get_matches_from(args);
-
-        define_tex_engine_var!(use_pdftex := matches, "pdftex", "pdf");
-        define_tex_engine_var!(use_xetex := matches, "xetex", "xe");
-        define_tex_engine_var!(use_luatex := matches, "luatex", "lua");
-        define_tex_engine_var!(use_latex := matches, "latex", "la");
-
-        let file_path = PathBuf::from(matches.get_one::<String>("INPUT").unwrap());
-        let tex_engine = if let Some(engine) = matches.get_one::<String>("ENGINE") {
-            engine.to_lowercase()
-        } else {
-            let engine = use_pdftex.to_string() + use_xetex + use_luatex + use_latex + "tex";
-            if !matches.get_flag("tex") && &engine == "tex" {
-                default_engine
-            } else {
-                engine
-            }
-        };
-        let is_conti_compile = matches.get_flag("autoCompile");
-        let is_view = matches.get_flag("view");
-        let is_remove_aux = matches.get_flag("removeAuxiliary");
-
-        Ok(Self {
-            file_path,
-            tex_engine,
-            is_conti_compile,
-            is_view,
-            is_remove_aux,
-        })
     }
 }
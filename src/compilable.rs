@@ -1,64 +1,142 @@
 use crate::error::{self, AutoTeXErr};
-use crate::texfile_info::TeXFileInfo;
-use std::ffi::OsStr;
-use std::process::Command;
+use crate::report;
+use std::ffi::OsString;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
 
-// Every types that implemented this trait can be compiled
-pub trait Compilable {
-    //TODO: Later, I will implement indivisual tex options
-    fn compile<S: AsRef<OsStr>>(&self, filename: &S) -> error::Result<bool>;
+// Arguments and working directory of one run of a program
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Invocation {
+    pub args: Vec<OsString>,
+    // Programs of a build run in the directory of the main file
+    pub current_dir: Option<PathBuf>,
+    // Give the program nothing to read so that it never waits for the user
+    pub null_stdin: bool,
 }
 
-// Some types that are compilable
-impl Compilable for &str {
-    fn compile<S: AsRef<OsStr>>(&self, filename: &S) -> error::Result<bool> {
-        Ok(Command::new(self).arg(filename).status()?.success())
+impl Invocation {
+    pub fn new<I, A>(args: I) -> Self
+    where
+        I: IntoIterator<Item = A>,
+        A: Into<OsString>,
+    {
+        Self {
+            args: args.into_iter().map(Into::into).collect(),
+            ..Self::default()
+        }
     }
 }
 
-impl Compilable for String {
-    fn compile<S: AsRef<OsStr>>(&self, filename: &S) -> error::Result<bool> {
-        self.as_str().compile(&filename)
-    }
+// What happened when a program ran
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Outcome {
+    pub command_line: String,
+    pub success: bool,
+    // None if the program was killed by a signal
+    pub exit_code: Option<i32>,
+    pub duration: Duration,
 }
 
-impl Compilable for TeXFileInfo {
-    fn compile<S: AsRef<OsStr>>(&self, _filename: &S) -> error::Result<bool> {
-        let only_idx = self
-            .filenames
-            .iter()
-            .filter(|x| x.extension() == Some(OsStr::new("idx")))
-            .map(|x| x.file_name());
-        let idx_compiled = if only_idx.clone().count() == 0 {
-            true
+// Every types that implemented this trait can be compiled
+pub trait Compilable {
+    fn compile(&self, invocation: &Invocation) -> error::Result<Outcome>;
+}
+
+// Quote arguments with spaces so that the command line can be pasted into a shell
+fn command_line(program: &str, args: &[OsString]) -> String {
+    let mut output = program.to_string();
+    for arg in args {
+        let arg = arg.to_string_lossy();
+        output.push(' ');
+        if arg.is_empty() || arg.contains(char::is_whitespace) {
+            output.push('\'');
+            output.push_str(&arg.replace('\'', "'\\''"));
+            output.push('\'');
         } else {
-            let mut status = true;
-            for file in only_idx {
-                match file {
-                    None => return Err(AutoTeXErr::NoneError),
-                    Some(ref f) => status = status && "makeindex".compile(f)?,
-                }
+            output.push_str(&arg);
+        }
+    }
+    output
+}
+
+// Copy the output of a program into the terminal
+fn forward<R, W>(mut reader: R, mut writer: W) -> thread::JoinHandle<()>
+where
+    R: Read + Send + 'static,
+    W: Write + Send + 'static,
+{
+    thread::spawn(move || {
+        let mut buf = [0u8; 8192];
+        while let Ok(n) = reader.read(&mut buf) {
+            if n == 0 {
+                break;
             }
-            status
-        };
+            // The terminal may be closed, but the program must not be blocked
+            let _ = writer.write_all(&buf[..n]).and_then(|()| writer.flush());
+        }
+    })
+}
 
-        let only_asy = self
-            .filenames
-            .iter()
-            .filter(|x| x.extension() == Some(OsStr::new("asy")))
-            .map(|x| x.file_name());
-        let asy_compiled = if only_asy.clone().count() == 0 {
-            true
-        } else {
-            let mut status = true;
-            for file in only_asy {
-                match file {
-                    None => return Err(AutoTeXErr::NoneError),
-                    Some(ref f) => status = status && "asy".compile(f)?,
-                }
+// Some types that are compilable
+impl Compilable for &str {
+    fn compile(&self, invocation: &Invocation) -> error::Result<Outcome> {
+        let mut command = Command::new(self);
+        command
+            .args(&invocation.args)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+        if let Some(dir) = &invocation.current_dir {
+            command.current_dir(dir);
+        }
+        if invocation.null_stdin {
+            command.stdin(Stdio::null());
+        }
+
+        let start = Instant::now();
+        let mut child = match command.spawn() {
+            Ok(child) => child,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                return Err(AutoTeXErr::MissingToolErr {
+                    program: self.to_string(),
+                    step: None,
+                    key: format!("tools.{}", self),
+                })
             }
-            status
+            Err(err) => return Err(AutoTeXErr::file(Path::new(&self.to_string()), err)),
         };
-        Ok(idx_compiled && asy_compiled)
+        // Output of programs is for humans too
+        let stdout = child.stdout.take().map(|out| {
+            let terminal: Box<dyn Write + Send> = if report::stdout_reserved() {
+                Box::new(io::stderr())
+            } else {
+                Box::new(io::stdout())
+            };
+            forward(out, terminal)
+        });
+        let stderr = child.stderr.take().map(|err| forward(err, io::stderr()));
+        let status = child
+            .wait()
+            .map_err(|err| AutoTeXErr::file(Path::new(&self.to_string()), err))?;
+        let duration = start.elapsed();
+
+        // Wait until the whole output is shown
+        for handle in stdout.into_iter().chain(stderr) {
+            let _ = handle.join();
+        }
+        Ok(Outcome {
+            command_line: command_line(self, &invocation.args),
+            success: status.success(),
+            exit_code: status.code(),
+            duration,
+        })
+    }
+}
+
+impl Compilable for String {
+    fn compile(&self, invocation: &Invocation) -> error::Result<Outcome> {
+        self.as_str().compile(invocation)
     }
 }
@@ -0,0 +1,733 @@
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+use yaml_rust::parser::{Event, MarkedEventReceiver, Parser};
+use yaml_rust::scanner::{Marker, TScalarStyle};
+use yaml_rust::{Yaml, YamlEmitter};
+
+use crate::bibliography::BibBackend;
+use crate::commands::{is_engine, LATEX_ENGINES, TEX_ENGINES};
+use crate::engines::EngineFlags;
+use crate::error::{self, AutoTeXErr};
+use crate::index::{IndexProcessor, IndexSettings};
+use crate::packages::Installer;
+use crate::pipeline::PipelineConfig;
+use crate::profile::Profile;
+use crate::tools::ToolPaths;
+
+// Maximum number of engine passes if nothing is configured
+pub const DEFAULT_MAX_RUNS: usize = 5;
+
+#[cfg(target_os = "linux")]
+pub const DEFAULT_PDF_VIEW: &str = "xdg-open";
+
+#[cfg(target_os = "macos")]
+pub const DEFAULT_PDF_VIEW: &str = "open";
+
+#[cfg(target_os = "windows")]
+pub const DEFAULT_PDF_VIEW: &str = "ii";
+
+// The config file must in at .config/autotex directory
+// and its name is config.yaml
+pub fn config_path() -> Option<PathBuf> {
+    dirs::config_dir().map(|dir| dir.join("autotex/config.yaml"))
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Null,
+    Bool(bool),
+    Int(i64),
+    Str(String),
+    // Other scalars like floats, kept as they are written
+    Other(String),
+    List(Vec<Node>),
+    Map(Vec<(String, Node)>),
+}
+
+// A value of the config file with its place in the file.
+// Keys of maps remember whether autotex read them, so that
+// the unknown ones can be reported.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Node {
+    pub value: Value,
+    pub line: usize,
+    // Place of the value like `engine.flags` or `pipeline.custom[0].name`
+    pub key: String,
+    used: Cell<bool>,
+}
+
+impl Node {
+    fn new(value: Value, line: usize, key: String) -> Self {
+        Self {
+            value,
+            line,
+            key,
+            used: Cell::new(false),
+        }
+    }
+
+    // Take the value of the key in a map. A null value is taken as a missing one.
+    pub fn get(&self, key: &str) -> Option<&Node> {
+        match &self.value {
+            Value::Map(entries) => entries
+                .iter()
+                .find(|(name, _)| name == key)
+                .map(|(_, node)| {
+                    node.used.set(true);
+                    node
+                })
+                .filter(|node| node.value != Value::Null),
+            _ => None,
+        }
+    }
+
+    // Every entry of a map, which are all taken as read
+    pub fn entries(&self) -> Vec<(&str, &Node)> {
+        match &self.value {
+            Value::Map(entries) => entries
+                .iter()
+                .map(|(name, node)| {
+                    node.used.set(true);
+                    (name.as_str(), node)
+                })
+                .collect(),
+            _ => vec![],
+        }
+    }
+
+    pub fn error(&self, message: String) -> AutoTeXErr {
+        AutoTeXErr::ConfigErr {
+            path: None,
+            line: self.line,
+            key: self.key.clone(),
+            message,
+        }
+    }
+
+    pub fn expected(&self, expected: &str) -> AutoTeXErr {
+        self.error(format!("expected {}", expected))
+    }
+
+    pub fn as_string(&self) -> error::Result<String> {
+        match &self.value {
+            Value::Str(s) => Ok(s.clone()),
+            _ => Err(self.expected("a string")),
+        }
+    }
+
+    pub fn as_bool(&self) -> error::Result<bool> {
+        match &self.value {
+            Value::Bool(b) => Ok(*b),
+            _ => Err(self.expected("true or false")),
+        }
+    }
+
+    pub fn as_positive(&self) -> error::Result<usize> {
+        match &self.value {
+            Value::Int(n) if *n > 0 => Ok(*n as usize),
+            _ => Err(self.expected("a positive integer")),
+        }
+    }
+
+    // A name of a TeX engine
+    pub fn as_engine(&self) -> error::Result<String> {
+        let name = self.as_string()?.to_lowercase();
+        if !is_engine(&name) {
+            return Err(self.error(AutoTeXErr::UnknownEngineErr(name).to_string()));
+        }
+        Ok(name)
+    }
+
+    // A string or a list of strings
+    pub fn as_string_list(&self) -> error::Result<Vec<String>> {
+        match &self.value {
+            Value::Str(s) => Ok(vec![s.clone()]),
+            Value::List(items) => items.iter().map(Node::as_string).collect(),
+            _ => Err(self.expected("a string or a list of strings")),
+        }
+    }
+
+    pub fn as_list(&self) -> error::Result<&[Node]> {
+        match &self.value {
+            Value::List(items) => Ok(items),
+            _ => Err(self.expected("a list")),
+        }
+    }
+
+    // Keys which autotex did not read
+    fn unknown_keys<'a>(&'a self, output: &mut Vec<&'a Node>) {
+        match &self.value {
+            Value::Map(entries) => {
+                for (_, node) in entries {
+                    if node.used.get() {
+                        node.unknown_keys(output);
+                    } else {
+                        output.push(node);
+                    }
+                }
+            }
+            Value::List(items) => {
+                for item in items {
+                    item.unknown_keys(output);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+pub fn optional_string(node: Option<&Node>) -> error::Result<Option<String>> {
+    node.map(Node::as_string).transpose()
+}
+
+pub fn string_list(node: Option<&Node>) -> error::Result<Vec<String>> {
+    Ok(node
+        .map(Node::as_string_list)
+        .transpose()?
+        .unwrap_or_default())
+}
+
+// Containers of the config file which are being read
+enum Frame {
+    List {
+        line: usize,
+        key: String,
+        anchor: usize,
+        items: Vec<Node>,
+    },
+    Map {
+        line: usize,
+        key: String,
+        anchor: usize,
+        entries: Vec<(String, Node)>,
+        // A key whose value is not read yet
+        pending: Option<String>,
+    },
+}
+
+// Build nodes from the events of the yaml parser, keeping their lines
+#[derive(Default)]
+struct Loader {
+    frames: Vec<Frame>,
+    anchors: HashMap<usize, Node>,
+    root: Option<Node>,
+}
+
+impl Loader {
+    fn child_key(&self) -> String {
+        let join = |parent: &str, key: &str| {
+            if parent.is_empty() {
+                key.to_string()
+            } else {
+                format!("{}.{}", parent, key)
+            }
+        };
+        match self.frames.last() {
+            None => String::new(),
+            Some(Frame::List { key, items, .. }) => format!("{}[{}]", key, items.len()),
+            Some(Frame::Map { key, pending, .. }) => {
+                join(key, pending.as_deref().unwrap_or_default())
+            }
+        }
+    }
+
+    fn expects_key(&self) -> bool {
+        matches!(self.frames.last(), Some(Frame::Map { pending: None, .. }))
+    }
+
+    fn push(&mut self, node: Node, anchor: usize) {
+        if anchor > 0 {
+            self.anchors.insert(anchor, node.clone());
+        }
+        match self.frames.last_mut() {
+            None => {
+                // Only the first document is read
+                if self.root.is_none() {
+                    self.root = Some(node);
+                }
+            }
+            Some(Frame::List { items, .. }) => items.push(node),
+            Some(Frame::Map {
+                entries, pending, ..
+            }) => match pending.take() {
+                Some(key) => entries.push((key, node)),
+                // A key which is not a string can never be read
+                None => *pending = Some(String::new()),
+            },
+        }
+    }
+}
+
+fn scalar_value(value: String, style: TScalarStyle) -> Value {
+    if style != TScalarStyle::Plain {
+        return Value::Str(value);
+    }
+    match Yaml::from_str(&value) {
+        Yaml::Null => Value::Null,
+        Yaml::Boolean(b) => Value::Bool(b),
+        Yaml::Integer(n) => Value::Int(n),
+        Yaml::String(s) => Value::Str(s),
+        _ => Value::Other(value),
+    }
+}
+
+impl MarkedEventReceiver for Loader {
+    fn on_event(&mut self, event: Event, mark: Marker) {
+        match event {
+            Event::Scalar(value, style, anchor, _) => {
+                if self.expects_key() {
+                    if let Some(Frame::Map { pending, .. }) = self.frames.last_mut() {
+                        *pending = Some(value);
+                    }
+                    return;
+                }
+                let node = Node::new(scalar_value(value, style), mark.line(), self.child_key());
+                self.push(node, anchor);
+            }
+            Event::Alias(anchor) => {
+                let node = self
+                    .anchors
+                    .get(&anchor)
+                    .cloned()
+                    .unwrap_or_else(|| Node::new(Value::Null, mark.line(), self.child_key()));
+                self.push(node, 0);
+            }
+            Event::SequenceStart(anchor) => {
+                let key = self.child_key();
+                self.frames.push(Frame::List {
+                    line: mark.line(),
+                    key,
+                    anchor,
+                    items: vec![],
+                });
+            }
+            Event::MappingStart(anchor) => {
+                let key = self.child_key();
+                self.frames.push(Frame::Map {
+                    line: mark.line(),
+                    key,
+                    anchor,
+                    entries: vec![],
+                    pending: None,
+                });
+            }
+            Event::SequenceEnd | Event::MappingEnd => {
+                let (node, anchor) = match self.frames.pop() {
+                    Some(Frame::List {
+                        line,
+                        key,
+                        anchor,
+                        items,
+                    }) => (Node::new(Value::List(items), line, key), anchor),
+                    Some(Frame::Map {
+                        line,
+                        key,
+                        anchor,
+                        entries,
+                        ..
+                    }) => (Node::new(Value::Map(entries), line, key), anchor),
+                    None => return,
+                };
+                self.push(node, anchor);
+            }
+            _ => {}
+        }
+    }
+}
+
+// Parse a yaml file into nodes. An empty file is an empty map.
+pub fn parse(contents: &str, path: &Path) -> error::Result<Node> {
+    let mut loader = Loader::default();
+    Parser::new(contents.chars())
+        .load(&mut loader, false)
+        .map_err(|err| AutoTeXErr::ScanErr(path.to_path_buf(), err))?;
+    Ok(loader
+        .root
+        .filter(|root| root.value != Value::Null)
+        .unwrap_or_else(|| Node::new(Value::Map(vec![]), 1, String::new())))
+}
+
+// Names of the config file of a project, which is searched
+// from the directory of the input file upward
+const PROJECT_CONFIGS: [&str; 2] = ["autotex.yaml", ".autotex/config.yaml"];
+
+pub fn find_project_config(start: &Path) -> Option<PathBuf> {
+    start.ancestors().find_map(|dir| {
+        PROJECT_CONFIGS
+            .iter()
+            .map(|name| dir.join(name))
+            .find(|path| path.is_file())
+    })
+}
+
+// Every setting which can be given by the config files
+#[derive(Debug, Clone, PartialEq)]
+pub struct Config {
+    // The files which were read, in the order they are merged
+    pub files: Vec<PathBuf>,
+    // The file to compile if none is given, set by a project config
+    pub main_file: Option<PathBuf>,
+    // The engine is chosen from the document if it is not given
+    pub engine: Option<String>,
+    pub max_runs: usize,
+    pub engine_flags: EngineFlags,
+    pub bib_backend: Option<BibBackend>,
+    pub index_settings: IndexSettings,
+    pub pipeline: PipelineConfig,
+    pub tools: ToolPaths,
+    pub installer: Option<Installer>,
+    pub pdf_viewer: String,
+    pub profiles: Vec<Profile>,
+    // Messages about keys which autotex does not know
+    pub warnings: Vec<String>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            files: vec![],
+            main_file: None,
+            engine: None,
+            max_runs: DEFAULT_MAX_RUNS,
+            engine_flags: EngineFlags::default(),
+            bib_backend: None,
+            index_settings: IndexSettings::default(),
+            pipeline: PipelineConfig::default(),
+            tools: ToolPaths::default(),
+            installer: None,
+            pdf_viewer: DEFAULT_PDF_VIEW.to_string(),
+            profiles: vec![],
+            warnings: vec![],
+        }
+    }
+}
+
+// Read `engine.flags` and `engine.<name>.flags` over the given flags
+fn merge_engine_flags(flags: &mut EngineFlags, engine: &Node) -> error::Result<()> {
+    if let Some(global) = engine.get("flags") {
+        flags.global = global.as_string_list()?;
+    }
+    for name in TEX_ENGINES.iter().chain(LATEX_ENGINES.iter()) {
+        if let Some(per_engine) = engine.get(name).and_then(|node| node.get("flags")) {
+            flags
+                .per_engine
+                .insert(name.to_string(), per_engine.as_string_list()?);
+        }
+    }
+    Ok(())
+}
+
+impl Config {
+    // Read the config file of the user, and then the config file of the project
+    // found from `start` over it. Missing files leave the defaults.
+    pub fn load(start: &Path) -> error::Result<Self> {
+        let mut config = Self::default();
+        if let Some(path) = config_path() {
+            config.read_file(&path, false)?;
+        }
+        if let Some(path) = find_project_config(start) {
+            config.read_file(&path, true)?;
+        }
+        Ok(config)
+    }
+
+    fn read_file(&mut self, path: &Path, is_project: bool) -> error::Result<()> {
+        let contents = match fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(()),
+            Err(err) => return Err(AutoTeXErr::file(path, err)),
+        };
+        let root = parse(&contents, path)?;
+        // The project config lives in the project or in its `.autotex` directory
+        let project_dir = path
+            .ancestors()
+            .skip(1)
+            .find(|dir| dir.file_name() != Some(".autotex".as_ref()))
+            .filter(|_| is_project);
+        self.merge(&root, project_dir)
+            .map_err(|err| err.in_file(path))?;
+
+        let mut unknown = vec![];
+        root.unknown_keys(&mut unknown);
+        self.warnings.extend(unknown.iter().map(|node| {
+            format!(
+                "{}:{}: unknown key `{}`",
+                path.display(),
+                node.line,
+                node.key
+            )
+        }));
+        self.files.push(path.to_path_buf());
+        Ok(())
+    }
+
+    // Take the keys which are in the file over the settings.
+    // The `pipeline` part is taken as a whole since its steps name its custom steps.
+    fn merge(&mut self, root: &Node, project_dir: Option<&Path>) -> error::Result<()> {
+        if !matches!(root.value, Value::Map(_)) {
+            return Err(root.expected("a map of settings"));
+        }
+
+        if let Some(dir) = project_dir {
+            if let Some(main) = root.get("main") {
+                self.main_file = Some(dir.join(main.as_string()?));
+            }
+        }
+
+        if let Some(engine) = root.get("engine") {
+            if let Some(main) = engine.get("main") {
+                self.engine = Some(main.as_engine()?);
+            }
+            if let Some(max_runs) = engine.get("max_runs") {
+                self.max_runs = max_runs.as_positive()?;
+            }
+            merge_engine_flags(&mut self.engine_flags, engine)?;
+        }
+
+        if let Some(backend) = root.get("bibliography").and_then(|bib| bib.get("backend")) {
+            self.bib_backend = BibBackend::from_name(&backend.as_string()?, &backend.key)
+                .map_err(|err| backend.error(err.to_string()))?;
+        }
+
+        if let Some(index) = root.get("index") {
+            if let Some(processor) = index.get("processor") {
+                self.index_settings.processor =
+                    IndexProcessor::from_name(&processor.as_string()?, &processor.key)
+                        .map_err(|err| processor.error(err.to_string()))?;
+            }
+            if let Some(style) = index.get("style") {
+                self.index_settings.style = Some(style.as_string()?);
+            }
+        }
+
+        if let Some(pipeline) = root.get("pipeline") {
+            self.pipeline = PipelineConfig::from_config(pipeline)?;
+        }
+        if let Some(tools) = root.get("tools") {
+            self.tools.merge(tools)?;
+        }
+        if let Some(install) = root.get("install") {
+            self.installer = Some(Installer::from_config(install)?);
+        }
+        if let Some(pdf) = root.get("pdf") {
+            self.pdf_viewer = pdf.as_string()?;
+        }
+        // A profile of the project replaces the one of the user with the same name
+        if let Some(profiles) = root.get("profiles") {
+            if !matches!(profiles.value, Value::Map(_)) {
+                return Err(profiles.expected("a map from names to profiles"));
+            }
+            for (name, node) in profiles.entries() {
+                let profile = Profile::from_config(name, node)?;
+                self.profiles.retain(|other| other.name != name);
+                self.profiles.push(profile);
+            }
+        }
+        Ok(())
+    }
+
+    // The settings as a yaml document which can be used as a config file
+    pub fn to_yaml(&self) -> Yaml {
+        let string = |s: &str| Yaml::String(s.to_string());
+        let list = |items: &[String]| Yaml::Array(items.iter().map(|s| string(s)).collect());
+        let map = |entries: Vec<(&str, Yaml)>| {
+            Yaml::Hash(entries.into_iter().map(|(k, v)| (string(k), v)).collect())
+        };
+
+        let mut engine = vec![];
+        if let Some(main) = &self.engine {
+            engine.push(("main", string(main)));
+        }
+        engine.push(("max_runs", Yaml::Integer(self.max_runs as i64)));
+        engine.push(("flags", list(&self.engine_flags.global)));
+        let mut per_engine: Vec<_> = self.engine_flags.per_engine.iter().collect();
+        per_engine.sort();
+        for (name, flags) in per_engine {
+            engine.push((name, map(vec![("flags", list(flags))])));
+        }
+
+        let mut index = vec![("processor", string(self.index_settings.processor.program()))];
+        if let Some(style) = &self.index_settings.style {
+            index.push(("style", string(style)));
+        }
+
+        let custom = self
+            .pipeline
+            .custom
+            .iter()
+            .map(|step| {
+                let mut entries = vec![
+                    ("name", string(&step.name)),
+                    ("command", string(&step.command)),
+                    ("args", list(&step.args)),
+                ];
+                if let Some(trigger) = &step.trigger {
+                    entries.push(("trigger", string(trigger)));
+                }
+                entries.push(("inputs", list(&step.inputs)));
+                entries.push(("outputs", list(&step.outputs)));
+                entries.push(("rerun", Yaml::Boolean(step.rerun)));
+                map(entries)
+            })
+            .collect();
+
+        let tools = self
+            .tools
+            .configured()
+            .into_iter()
+            .map(|(name, path)| (name, string(path)))
+            .collect();
+
+        let mut root = vec![];
+        if let Some(main) = &self.main_file {
+            root.push(("main", string(&main.to_string_lossy())));
+        }
+        root.extend(vec![
+            ("engine", map(engine)),
+            (
+                "bibliography",
+                map(vec![(
+                    "backend",
+                    string(self.bib_backend.map_or("auto", |backend| backend.program())),
+                )]),
+            ),
+            ("index", map(index)),
+            (
+                "pipeline",
+                map(vec![
+                    ("steps", list(&self.pipeline.steps)),
+                    ("disable", list(&self.pipeline.disabled)),
+                    ("custom", Yaml::Array(custom)),
+                    (
+                        "pdfa",
+                        map(vec![
+                            ("command", string(&self.pipeline.pdfa.command)),
+                            ("args", list(&self.pipeline.pdfa.args)),
+                        ]),
+                    ),
+                ]),
+            ),
+            ("tools", map(tools)),
+        ]);
+        if let Some(installer) = &self.installer {
+            root.push((
+                "install",
+                map(vec![("command", string(&installer.command.join(" ")))]),
+            ));
+        }
+        root.push(("pdf", string(&self.pdf_viewer)));
+        if !self.profiles.is_empty() {
+            let profiles = self
+                .profiles
+                .iter()
+                .map(|profile| {
+                    let mut entries = vec![];
+                    if let Some(engine) = &profile.engine {
+                        entries.push(("engine", string(engine)));
+                    }
+                    if let Some(max_runs) = profile.max_runs {
+                        entries.push(("max_runs", Yaml::Integer(max_runs as i64)));
+                    }
+                    entries.push(("flags", list(&profile.flags)));
+                    if let Some(steps) = &profile.steps {
+                        entries.push(("steps", list(steps)));
+                    }
+                    if let Some(output) = &profile.output {
+                        entries.push(("output", string(output)));
+                    }
+                    if let Some(preamble) = &profile.preamble {
+                        entries.push(("preamble", string(preamble)));
+                    }
+                    (profile.name.as_str(), map(entries))
+                })
+                .collect();
+            root.push(("profiles", map(profiles)));
+        }
+        map(root)
+    }
+
+    pub fn profile(&self, name: &str) -> error::Result<Profile> {
+        self.profiles
+            .iter()
+            .find(|profile| profile.name == name)
+            .cloned()
+            .ok_or_else(|| {
+                AutoTeXErr::invalid_value("--profile", name, "a name in `profiles` of the config")
+            })
+    }
+
+    // Print the settings as a yaml document after the files they are read from
+    pub fn show(&self) -> String {
+        let mut output: String = self
+            .files
+            .iter()
+            .map(|file| format!("# {}\n", file.display()))
+            .collect();
+        // Writing into a String never fails
+        let _ = YamlEmitter::new(&mut output).dump(&self.to_yaml());
+        output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_str(contents: &str) -> Node {
+        parse(contents, Path::new("config.yaml")).unwrap()
+    }
+
+    #[test]
+    fn nodes_keep_lines_and_keys() {
+        let root = parse_str(
+            "engine:\n  main: lualatex\n  max_runs: 3\npipeline:\n  custom:\n    - name: sage\n      command: sage\n",
+        );
+        let max_runs = root.get("engine").unwrap().get("max_runs").unwrap();
+        assert_eq!(max_runs.value, Value::Int(3));
+        assert_eq!(max_runs.line, 3);
+        assert_eq!(max_runs.key, "engine.max_runs");
+
+        let custom = root.get("pipeline").unwrap().get("custom").unwrap();
+        let command = custom.as_list().unwrap()[0].get("command").unwrap();
+        assert_eq!(command.line, 7);
+        assert_eq!(command.key, "pipeline.custom[0].command");
+    }
+
+    #[test]
+    fn empty_file_is_empty_map() {
+        assert_eq!(parse_str("").value, Value::Map(vec![]));
+        assert_eq!(parse_str("# only a comment\n").value, Value::Map(vec![]));
+    }
+
+    #[test]
+    fn wrong_value_reports_its_line() {
+        let root = parse_str("# settings\nengine:\n  max_runs: zero\n");
+        match Config::default().merge(&root, None) {
+            Err(AutoTeXErr::ConfigErr { line, key, .. }) => {
+                assert_eq!(line, 3);
+                assert_eq!(key, "engine.max_runs");
+            }
+            other => panic!("expected a config error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn unknown_keys_are_reported() {
+        let root = parse_str(
+            "engine:\n  main: pdflatex\n  colour: red\nviewer: evince\ntools:\n  bibtex: /usr/bin/bibtex\n",
+        );
+        Config::default().merge(&root, None).unwrap();
+
+        let mut unknown = vec![];
+        root.unknown_keys(&mut unknown);
+        let found: Vec<(&str, usize)> = unknown
+            .iter()
+            .map(|node| (node.key.as_str(), node.line))
+            .collect();
+        assert_eq!(found, vec![("engine.colour", 3), ("viewer", 4)]);
+    }
+}
@@ -0,0 +1,166 @@
+use std::path::{Path, PathBuf};
+
+use crate::error;
+use crate::tex_source::{self, TeXCommand};
+
+// Commands which read another tex file
+const INCLUDE_COMMANDS: [&str; 4] = ["input", "include", "subfile", "subfileinclude"];
+
+// Commands which read a file named with its extension, like a code listing
+const FILE_COMMANDS: [&str; 4] = [
+    "addbibresource",
+    "lstinputlisting",
+    "verbatiminput",
+    "includepdf",
+];
+
+// Extensions which \includegraphics tries for a name without one
+const GRAPHICS_EXTENSIONS: [&str; 6] = ["pdf", "png", "jpg", "jpeg", "eps", "mps"];
+
+fn with_default_extension(path: &str, extension: &str) -> PathBuf {
+    let path = PathBuf::from(path.trim());
+    if path.extension().is_none() {
+        path.with_extension(extension)
+    } else {
+        path
+    }
+}
+
+// An argument like `|"kpsewhich foo"` runs a shell command instead of reading a file
+fn is_file_name(argument: &str) -> bool {
+    !argument.is_empty() && !argument.starts_with('|') && !argument.contains('\\')
+}
+
+fn commands<'a>(source: &'a str, names: &'a [&str]) -> impl Iterator<Item = TeXCommand> + 'a {
+    names
+        .iter()
+        .flat_map(move |name| tex_source::find_commands(source, name))
+}
+
+// Files read by \input, \include and \subfile.
+// TeX reads them relative to the directory of the root.
+pub fn included_files(source: &str) -> Vec<PathBuf> {
+    commands(source, &INCLUDE_COMMANDS)
+        .filter(|cmd| is_file_name(cmd.argument.trim()))
+        .map(|cmd| with_default_extension(&cmd.argument, "tex"))
+        .collect()
+}
+
+// Files given as a list of names like `\usepackage{style, macros}`,
+// with the extension which the command adds
+fn listed_files(source: &str, names: &[&str], extension: &str) -> Vec<PathBuf> {
+    commands(source, names)
+        .flat_map(|cmd| {
+            tex_source::split_list(&cmd.argument)
+                .filter(|name| is_file_name(name))
+                .map(|name| with_default_extension(name, extension))
+                .collect::<Vec<_>>()
+        })
+        .collect()
+}
+
+// Packages and classes in the directory of the document. Those of the
+// TeX distribution are not found there, so they are not watched.
+fn local_packages(source: &str, dir: &Path) -> Vec<PathBuf> {
+    let packages = listed_files(source, &["usepackage", "RequirePackage"], "sty");
+    let classes = listed_files(source, &["documentclass", "LoadClass"], "cls");
+    packages
+        .into_iter()
+        .chain(classes)
+        .filter(|path| dir.join(path).is_file())
+        .collect()
+}
+
+// Directories of `\graphicspath{{figures/}{images/}}`
+fn graphics_dirs(source: &str) -> Vec<PathBuf> {
+    tex_source::find_commands(source, "graphicspath")
+        .iter()
+        .flat_map(|cmd| {
+            cmd.argument
+                .split('}')
+                .map(|dir| dir.trim().trim_start_matches('{').trim())
+                .filter(|dir| !dir.is_empty())
+                .map(PathBuf::from)
+                .collect::<Vec<_>>()
+        })
+        .collect()
+}
+
+// Images of \includegraphics, searched like graphicx does
+fn graphics_files(source: &str, dir: &Path, graphics_dirs: &[PathBuf]) -> Vec<PathBuf> {
+    let search_dirs: Vec<&Path> = std::iter::once(Path::new(""))
+        .chain(graphics_dirs.iter().map(PathBuf::as_path))
+        .collect();
+    tex_source::find_commands(source, "includegraphics")
+        .iter()
+        .filter(|cmd| is_file_name(cmd.argument.trim()))
+        .filter_map(|cmd| {
+            let name = Path::new(cmd.argument.trim());
+            let candidates: Vec<PathBuf> = if name.extension().is_some() {
+                vec![name.to_path_buf()]
+            } else {
+                GRAPHICS_EXTENSIONS
+                    .iter()
+                    .map(|ext| name.with_extension(ext))
+                    .collect()
+            };
+            search_dirs
+                .iter()
+                .flat_map(|search_dir| candidates.iter().map(move |file| search_dir.join(file)))
+                .find(|file| dir.join(file).is_file())
+        })
+        .collect()
+}
+
+// Files which are not TeX sources, like bibliographies, images and listings
+fn data_files(source: &str, dir: &Path, graphics_dirs: &[PathBuf]) -> Vec<PathBuf> {
+    let bibliographies = listed_files(source, &["bibliography"], "bib");
+    let styles = listed_files(source, &["bibliographystyle"], "bst")
+        .into_iter()
+        .filter(|path| dir.join(path).is_file());
+    let files = commands(source, &FILE_COMMANDS)
+        .map(|cmd| cmd.argument.trim().to_string())
+        .filter(|name| is_file_name(name))
+        .map(PathBuf::from);
+    bibliographies
+        .into_iter()
+        .chain(styles)
+        .chain(files)
+        .chain(graphics_files(source, dir, graphics_dirs))
+        .collect()
+}
+
+// Take the files which the document in `dir` reads, starting from its main file.
+// A missing tex file or bibliography is kept, so that creating it is noticed.
+pub fn watch_files(dir: &Path, mainfile: &Path) -> error::Result<Vec<PathBuf>> {
+    let mut files: Vec<PathBuf> = vec![];
+    let mut sources = vec![];
+    let mut queue = vec![mainfile.to_path_buf()];
+    while let Some(file) = queue.pop() {
+        if files.contains(&file) {
+            continue;
+        }
+        if let Some(source) = tex_source::read_if_exists(dir.join(&file))? {
+            let source = tex_source::strip_comments(&source);
+            let mut children = included_files(&source);
+            children.extend(local_packages(&source, dir));
+            // Read the children in the order of the source
+            queue.extend(children.into_iter().rev());
+            sources.push(source);
+        }
+        files.push(file);
+    }
+
+    let graphics_dirs: Vec<PathBuf> = sources
+        .iter()
+        .flat_map(|source| graphics_dirs(source))
+        .collect();
+    for source in sources.iter() {
+        for file in data_files(source, dir, &graphics_dirs) {
+            if !files.contains(&file) {
+                files.push(file);
+            }
+        }
+    }
+    Ok(files.into_iter().map(|file| dir.join(file)).collect())
+}
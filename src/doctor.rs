@@ -0,0 +1,182 @@
+use std::env;
+use std::process::{Command, Stdio};
+
+use crate::commands::{self, LATEX_ENGINES, TEX_ENGINES};
+use crate::config::{self, Config};
+use crate::error::{self, AutoTeXErr};
+use crate::preamble;
+use crate::texfile_info;
+use crate::tools::{self, ToolPaths};
+
+// Tools of the pipeline which are checked after the engines
+const PIPELINE_TOOLS: [&str; 9] = [
+    "bibtex",
+    "biber",
+    "makeindex",
+    "asy",
+    "xindy",
+    "upmendex",
+    "makeglossaries",
+    "bib2gls",
+    "verapdf",
+];
+
+// First line of `program --version`
+fn version_of(program: &str) -> Option<String> {
+    let output = Command::new(program)
+        .arg("--version")
+        .stdin(Stdio::null())
+        .output()
+        .ok()?;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    stdout
+        .lines()
+        .chain(stderr.lines())
+        .map(str::trim)
+        .find(|line| !line.is_empty())
+        .map(str::to_string)
+}
+
+// Check the config file, and return the settings read from it if it is valid
+fn check_config(problems: &mut usize) -> Option<Config> {
+    match config::config_path() {
+        Some(path) if path.exists() => say!("Config file: {}", path.display()),
+        Some(path) => {
+            say!("Config file: {}", path.display());
+            say!("  not found, defaults are used");
+        }
+        None => say!("Config file: no config directory is found"),
+    }
+    let current_dir = env::current_dir().unwrap_or_default();
+    if let Some(path) = config::find_project_config(&current_dir) {
+        say!("Project config file: {}", path.display());
+    }
+    match Config::load(&current_dir) {
+        Ok(config) => {
+            for warning in config.warnings.iter() {
+                say!("  warning: {}", warning);
+                *problems += 1;
+            }
+            if !config.files.is_empty() {
+                say!("  valid");
+            }
+            Some(config)
+        }
+        Err(err) => {
+            say!("  error: {}", err);
+            *problems += 1;
+            None
+        }
+    }
+}
+
+fn check_tools(tools: &ToolPaths, main_engine: &str, problems: &mut usize) {
+    say!("Tools:");
+    let names = TEX_ENGINES
+        .iter()
+        .chain(LATEX_ENGINES.iter())
+        .chain(PIPELINE_TOOLS.iter());
+    for name in names {
+        let program = tools.get(name);
+        match tools::find_program(program) {
+            Some(path) => say!(
+                "  {:<15} {}  {}",
+                name,
+                path.display(),
+                version_of(program).unwrap_or_default()
+            ),
+            None => {
+                if program != *name {
+                    say!("  {:<15} {} is not found", name, program);
+                } else {
+                    say!("  {:<15} not found (set `tools.{}`)", name, name);
+                }
+                if *name == main_engine {
+                    say!("  error: the default engine `{}` is not found", name);
+                    *problems += 1;
+                }
+            }
+        }
+    }
+}
+
+fn check_viewer(viewer: &str, problems: &mut usize) {
+    say!("PDF viewer:");
+    // The viewer is a command of PowerShell on windows
+    if cfg!(windows) {
+        say!("  {}", viewer);
+        return;
+    }
+    match tools::find_program(viewer) {
+        Some(path) => say!("  {:<15} {}", viewer, path.display()),
+        None => {
+            say!("  {:<15} not found (set `pdf`)", viewer);
+            *problems += 1;
+        }
+    }
+}
+
+// The engine used when `engine.main` is not set, and why. It is chosen from
+// the main file of the project as a build does, and it is pdftex without one.
+fn default_engine(config: Option<&Config>) -> (String, String) {
+    let tex_info = config
+        .and_then(|config| config.main_file.as_ref())
+        .and_then(|main| texfile_info::get_files_info(main).ok());
+    let tex_info = match tex_info {
+        Some(tex_info) => tex_info,
+        None => return ("pdftex".to_string(), "no main file is set".to_string()),
+    };
+    if let Some((engine, source)) = commands::declared_engine(&tex_info.magic_comments) {
+        return (engine, format!("the main file declares it by {}", source));
+    }
+    match preamble::infer_engine(&tex_info) {
+        Ok(inference) => (inference.engine.to_string(), inference.reason),
+        Err(_) => (
+            "pdftex".to_string(),
+            "the main file cannot be read".to_string(),
+        ),
+    }
+}
+
+fn print_config(config: &Config, default_engine: &(String, String)) {
+    say!("Effective configuration:");
+    let mut config = config.clone();
+    if config.engine.is_none() {
+        let (engine, reason) = default_engine;
+        say!(
+            "  # `engine.main` is not set, so {} is used since {}",
+            engine,
+            reason
+        );
+        config.engine = Some(engine.clone());
+    }
+    // The yaml document starts with a `---` marker after the names of the files
+    for line in config.show().lines().filter(|line| *line != "---") {
+        say!("  {}", line);
+    }
+}
+
+// Check what autotex needs, and print what is wrong
+pub fn run_doctor() -> error::Result<()> {
+    let mut problems = 0;
+    let config = check_config(&mut problems);
+    let defaults = Config::default();
+    let checked = config.as_ref().unwrap_or(&defaults);
+    let default_engine = default_engine(config.as_ref());
+    let main_engine = checked.engine.as_deref().unwrap_or(&default_engine.0);
+    check_tools(&checked.tools, main_engine, &mut problems);
+    check_viewer(&checked.pdf_viewer, &mut problems);
+    if let Some(config) = &config {
+        print_config(config, &default_engine);
+    }
+
+    // Problems make the exit code fail, so that scripts and CI can check them
+    match problems {
+        0 => {
+            say!("No problems found.");
+            Ok(())
+        }
+        n => Err(AutoTeXErr::DoctorErr(n)),
+    }
+}
@@ -1,13 +1,138 @@
-use crate::compilable::Compilable;
+use crate::build_state::BuildState;
+use crate::commands::AutoTeXCommand;
+use crate::compilable::{Compilable, Invocation, Outcome};
 use crate::error::{self, AutoTeXErr};
+use crate::pipeline::{BuildContext, Pipeline, PipelineOutcome};
+use crate::profile::Profile;
+use crate::tex_log::{self, Diagnostic};
+use crate::tex_source;
 use crate::texfile_info::TeXFileInfo;
-use std::env;
-use std::ffi::OsStr;
+use std::collections::HashMap;
+use std::path::Path;
+use std::time::Duration;
 
-// Return a unit value if the tex engine is failed
+// Record what the program did, and stop the build if it failed
 macro_rules! quit_if_failed {
-    ($e: expr; $($es: expr),*) => {
-        if !$e.compile($($es,)*)? { return Ok(false); }
+    ($report: ident, $step: expr; $e: expr, $invocation: expr) => {
+        if !$report.record($step, $e.compile($invocation)?) {
+            return Ok(());
+        }
+    };
+}
+
+// A program which ran in a build, and the step it belongs to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StepRecord {
+    pub step: String,
+    pub outcome: Outcome,
+}
+
+// Everything that happened in one build
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BuildReport {
+    pub success: bool,
+    pub passes: usize,
+    // false if the log still asks for another pass
+    pub stable: bool,
+    pub steps: Vec<StepRecord>,
+    pub diagnostics: Vec<Diagnostic>,
+    // Output file and its number of pages written in the log file
+    pub output: Option<String>,
+    pub pages: Option<usize>,
+}
+
+impl BuildReport {
+    pub fn new() -> Self {
+        Self {
+            success: true,
+            stable: true,
+            ..Self::default()
+        }
+    }
+
+    // Record what a program did, and return whether it succeeded
+    pub fn record(&mut self, step: &str, outcome: Outcome) -> bool {
+        let success = outcome.success;
+        self.success = self.success && success;
+        self.steps.push(StepRecord {
+            step: step.to_string(),
+            outcome,
+        });
+        success
+    }
+
+    pub fn failed_step(&self) -> Option<&StepRecord> {
+        self.steps.iter().find(|record| !record.outcome.success)
+    }
+
+    pub fn duration(&self) -> Duration {
+        self.steps
+            .iter()
+            .map(|record| record.outcome.duration)
+            .sum()
+    }
+
+    // The error which stops autotex if this build failed
+    pub fn failure(&self) -> Option<AutoTeXErr> {
+        let failed = self.failed_step()?;
+        let command = failed.outcome.command_line.clone();
+        let exit_code = failed.outcome.exit_code;
+        Some(match failed.step.as_str() {
+            "engine" => AutoTeXErr::CompileErr { command, exit_code },
+            "bibliography" => AutoTeXErr::BibliographyErr { command, exit_code },
+            step => AutoTeXErr::ToolErr {
+                step: step.to_string(),
+                command,
+                exit_code,
+            },
+        })
+    }
+
+    // `dir` is the directory of the main file, where the sources of errors are read
+    pub fn print_summary(&self, dir: &Path) {
+        tex_log::print_summary(&self.diagnostics, dir);
+        if let Some(failure) = self.failure() {
+            say!("{}.", failure);
+        } else if !self.stable {
+            say!(
+                "Stopped after {} passes, but the document is not stable yet.",
+                self.passes
+            );
+        } else {
+            say!(
+                "Compiled with {} pass(es) in {:.2}s.",
+                self.passes,
+                self.duration().as_secs_f64()
+            );
+        }
+    }
+}
+
+// Interaction mode of engines in the continuous compiling
+const WATCH_INTERACTION: &str = "-interaction=nonstopmode";
+const CONTEXT_WATCH_INTERACTION: &str = "--nonstopmode";
+
+// Command line options of engines given by config and command line.
+// They are passed in the order of global, per engine, profile, magic comment
+// `% !TEX options = ...` and command line.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct EngineFlags {
+    pub global: Vec<String>,
+    pub per_engine: HashMap<String, Vec<String>>,
+    pub profile: Vec<String>,
+    pub cli: Vec<String>,
+}
+
+impl EngineFlags {
+    pub fn for_engine(&self, engine: &str, tex_info: &TeXFileInfo) -> Vec<String> {
+        let mut output = self.global.clone();
+        output.extend(self.per_engine.get(engine).into_iter().flatten().cloned());
+        output.extend(self.profile.iter().cloned());
+        if let Some(options) = tex_info.magic_comments.get("options") {
+            output.extend(options.split_whitespace().map(str::to_string));
+        }
+        output.extend(self.cli.iter().cloned());
+        output
     }
 }
 
@@ -16,15 +141,21 @@ macro_rules! quit_if_failed {
 #[derive(Debug)]
 pub struct TeXEngine<E: Compilable> {
     engine: E,
+    name: String,
     is_tex: bool,
+    is_watch: bool,
+    max_runs: usize,
+    flags: EngineFlags,
+    profile: Option<Profile>,
+    pipeline: Pipeline,
 }
 
 impl<E> Compilable for TeXEngine<E>
 where
     E: Compilable,
 {
-    fn compile<S: AsRef<OsStr>>(&self, filename: &S) -> error::Result<bool> {
-        self.engine.compile(&filename)
+    fn compile(&self, invocation: &Invocation) -> error::Result<Outcome> {
+        self.engine.compile(invocation)
     }
 }
 
@@ -32,61 +163,136 @@ impl<E> TeXEngine<E>
 where
     E: Compilable,
 {
-    fn new(engine: E, is_tex: bool) -> Self {
-        Self { engine, is_tex }
+    fn new(engine: E, is_tex: bool, args: &AutoTeXCommand) -> error::Result<Self> {
+        Ok(Self {
+            engine,
+            name: args.tex_engine.clone(),
+            is_tex,
+            is_watch: args.is_conti_compile,
+            max_runs: args.max_runs,
+            flags: args.engine_flags.clone(),
+            profile: args.profile.clone(),
+            pipeline: Pipeline::new(args)?,
+        })
+    }
+
+    // An engine must never wait for the user in the continuous compiling
+    fn invocation(&self, tex_info: &TeXFileInfo) -> Invocation {
+        let mut args = self.flags.for_engine(&self.name, tex_info);
+        let has_interaction = args.iter().any(|arg| {
+            let arg = arg.trim_start_matches('-');
+            arg.starts_with("interaction") || arg == "nonstopmode" || arg == "batchmode"
+        });
+        if self.is_watch && !has_interaction {
+            let interaction = match self.name.as_str() {
+                "context" => CONTEXT_WATCH_INTERACTION,
+                _ => WATCH_INTERACTION,
+            };
+            args.insert(0, interaction.to_string());
+        }
+        if tex_info.jobname != tex_info.mainfile {
+            args.push(format!("-jobname={}", tex_info.jobname.to_string_lossy()));
+        }
+        // The preamble of a profile runs before the main file is read
+        match self
+            .profile
+            .as_ref()
+            .and_then(|profile| profile.preamble.as_ref())
+        {
+            Some(preamble) => args.push(format!(
+                "{}\\input{{{}}}",
+                preamble,
+                tex_info.get_main_tex_file()
+            )),
+            None => args.push(tex_info.get_main_tex_file()),
+        }
+
+        let mut invocation = Invocation::new(args);
+        invocation.current_dir = Some(tex_info.current_dir.clone());
+        invocation.null_stdin = self.is_watch;
+        invocation
     }
 
     // Main function of compiling TeX
-    pub fn run_engine(&self, tex_info: &TeXFileInfo) -> error::Result<bool> {
-        let mainfile = tex_info.get_main_tex_file();
-        env::set_current_dir(&tex_info.current_dir)?;
-        quit_if_failed!(self; &mainfile);
-        if self.is_tex {
-            if tex_info.asymptote_exists {
-                quit_if_failed!(tex_info; &"");
-                quit_if_failed!(self; &mainfile);
-            } else {
-                quit_if_failed!(self; &mainfile);
-            }
-        } else {
-            match (
-                tex_info.bibtex_exists,
-                tex_info.mkindex_exists || tex_info.asymptote_exists,
-            ) {
-                (false, false) => {
-                    quit_if_failed!(self; &mainfile);
-                }
-                (true, false) => {
-                    quit_if_failed!("bibtex"; &tex_info.mainfile);
-                    quit_if_failed!(self; &mainfile);
-                    quit_if_failed!(self; &mainfile);
-                }
-                (false, true) => {
-                    quit_if_failed!(tex_info; &"");
-                    quit_if_failed!(self; &mainfile);
-                    quit_if_failed!(self; &mainfile);
-                }
-                (true, true) => {
-                    quit_if_failed!("bibtex"; &tex_info.mainfile);
-                    quit_if_failed!(tex_info; &"");
-                    quit_if_failed!(self; &mainfile);
-                    quit_if_failed!(self; &mainfile);
-                }
+    // The report keeps what happened before an error like a missing tool.
+    pub fn run_engine(
+        &self,
+        tex_info: &TeXFileInfo,
+        report: &mut BuildReport,
+    ) -> error::Result<()> {
+        let logfile = tex_info.in_dir(tex_info.get_main_log_file());
+        let mut ctx = BuildContext {
+            tex_info,
+            is_tex: self.is_tex,
+            passes: 0,
+            state: BuildState::load(&tex_info.current_dir, &tex_info.jobname)?,
+        };
+
+        let result = self.run_passes(&mut ctx, report);
+        // Tools which succeeded before a failure keep their hashes
+        ctx.state.save()?;
+        read_log(report, &logfile, &tex_info.current_dir)?;
+        result?;
+        report.print_summary(&tex_info.current_dir);
+        Ok(())
+    }
+
+    // Run the engine and the pipeline until the document is stable
+    fn run_passes(
+        &self,
+        ctx: &mut BuildContext<'_>,
+        report: &mut BuildReport,
+    ) -> error::Result<()> {
+        let logfile = ctx.tex_info.in_dir(ctx.tex_info.get_main_log_file());
+        let pass = self.invocation(ctx.tex_info);
+
+        // The first pass writes the files that the other tools read
+        quit_if_failed!(report, "engine"; self, &pass);
+        report.passes = 1;
+
+        // Tools of the pipeline read what the last pass wrote, and their outputs
+        // are read by the next pass. A pass can ask for a tool again, like a
+        // citation which appears only after the bibliography is typeset, so the
+        // pipeline runs after every pass. Tools with unchanged inputs are skipped.
+        loop {
+            ctx.passes = report.passes;
+            let tools_ran = match self.pipeline.run(ctx, report)? {
+                PipelineOutcome::Failed => return Ok(()),
+                PipelineOutcome::Finished { needs_rerun } => needs_rerun,
+            };
+            let needs_rerun = tools_ran || tex_log::needs_rerun(&logfile)?;
+            report.stable = !needs_rerun;
+            if !needs_rerun || report.passes >= self.max_runs {
+                break;
             }
+            quit_if_failed!(report, "engine"; self, &pass);
+            report.passes += 1;
         }
-        Ok(true)
+
+        // Checks like PDF/A read the pdf file of the last pass
+        self.pipeline.check(ctx, report)
     }
 }
 
-// Take an appropriate TeX engine from an option
-pub fn take_engine(engine: &str) -> error::Result<TeXEngine<String>> {
-    match engine {
-        "pdftex" | "xetex" | "luatex" | "tex" | "plaintex" => {
-            Ok(TeXEngine::new(engine.to_string(), true))
-        }
-        "pdflatex" | "xelatex" | "lualatex" | "latex" | "plainlatex" => {
-            Ok(TeXEngine::new(engine.to_string(), false))
-        }
-        _ => Err(AutoTeXErr::InvalidOptionErr),
+// Read errors and warnings from the log file
+fn read_log(report: &mut BuildReport, logfile: &Path, dir: &Path) -> error::Result<()> {
+    let log = tex_source::read_if_exists(logfile)?.unwrap_or_default();
+    report.diagnostics = tex_log::parse_log(&log, dir);
+    if let Some((output, pages)) = tex_log::output_info(&log) {
+        report.output = Some(output);
+        report.pages = pages;
     }
+    Ok(())
+}
+
+// Take an appropriate TeX engine from an option
+// The engine is searched before the first build so that a missing one stops autotex early
+pub fn take_engine(args: &AutoTeXCommand) -> error::Result<TeXEngine<String>> {
+    let engine = args.tex_engine.as_str();
+    let is_tex = match engine {
+        "pdftex" | "xetex" | "luatex" | "tex" | "plaintex" | "context" => true,
+        "pdflatex" | "xelatex" | "lualatex" | "latex" | "plainlatex" => false,
+        _ => return Err(AutoTeXErr::UnknownEngineErr(engine.to_string())),
+    };
+    TeXEngine::new(args.tools.require(engine, "engine")?, is_tex, args)
 }
@@ -1,44 +1,222 @@
+use std::env;
 use std::fmt;
 use std::io;
+use std::path::{Path, PathBuf};
 use yaml_rust::scanner::ScanError;
 
+// Exit codes of the autotex process.
+// clap exits with 2 for a wrong command line by itself.
+pub const EXIT_FAILURE: i32 = 1;
+pub const EXIT_USAGE: i32 = 2;
+pub const EXIT_CONFIG: i32 = 3;
+pub const EXIT_MISSING_TOOL: i32 = 4;
+pub const EXIT_COMPILE: i32 = 5;
+pub const EXIT_BIBLIOGRAPHY: i32 = 6;
+pub const EXIT_TOOL: i32 = 7;
+pub const EXIT_IO: i32 = 8;
+pub const EXIT_DOCTOR: i32 = 9;
+
 #[derive(Debug)]
+#[allow(clippy::enum_variant_names)]
 pub enum AutoTeXErr {
-    IOErr(io::Error),
-    ScanErr(ScanError),
+    // An I/O error on the given file
+    FileErr(PathBuf, io::Error),
+    // Ctrl+C cannot be caught in the continuous compiling
+    SignalErr(io::Error),
+    // The config file is not a valid yaml
+    ScanErr(PathBuf, ScanError),
     CommandErr(clap::Error),
-    NoneError,
-    NoFilenameInputErr,
-    TakeFilesErr,
-    CannotShowPdfErr,
-    InvalidOptionErr,
+    NoFilenameInputErr(PathBuf),
+    // Options which cannot be given together
+    UsageErr(String),
+    #[cfg_attr(not(windows), allow(dead_code))]
+    CannotShowPdfErr(String),
+    UnknownEngineErr(String),
+    // A value of a config key, a command line option or a magic comment
+    // which autotex does not know
+    InvalidValueErr {
+        key: String,
+        value: String,
+        expected: &'static str,
+    },
+    // A wrong value in the config file, with the line of the value
+    ConfigErr {
+        path: Option<PathBuf>,
+        line: usize,
+        key: String,
+        message: String,
+    },
+    // A program which is not found, the pipeline step which needs it
+    // and the config key which gives its path
+    MissingToolErr {
+        program: String,
+        step: Option<String>,
+        key: String,
+    },
+    // The engine failed in the build
+    CompileErr {
+        command: String,
+        exit_code: Option<i32>,
+    },
+    BibliographyErr {
+        command: String,
+        exit_code: Option<i32>,
+    },
+    // Another program of the pipeline failed
+    ToolErr {
+        step: String,
+        command: String,
+        exit_code: Option<i32>,
+    },
+    // `autotex doctor` found problems, and how many
+    DoctorErr(usize),
+}
+
+// `exit code 1`, or `a signal` if the program was killed
+fn status(exit_code: &Option<i32>) -> String {
+    match exit_code {
+        Some(code) => format!("exit code {}", code),
+        None => "a signal".to_string(),
+    }
 }
 
 impl fmt::Display for AutoTeXErr {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         use AutoTeXErr::*;
         match *self {
-            IOErr(ref e) => e.fmt(f),
-            ScanErr(ref e) => e.fmt(f),
+            FileErr(ref path, ref e) => write!(f, "{}: {}", path.display(), e),
+            SignalErr(ref e) => write!(f, "Cannot catch Ctrl+C: {}", e),
+            ScanErr(ref path, ref e) => write!(f, "Cannot read {}: {}", path.display(), e),
             CommandErr(ref e) => e.fmt(f),
-            NoneError => write!(f, "NoneError"),
-            NoFilenameInputErr => write!(f, "There is no filename to compile"),
-            TakeFilesErr => write!(f, "Cannot make a list of tex relative files"),
-            CannotShowPdfErr => write!(f, "Cannot show pdf"),
-            InvalidOptionErr => write!(f, "No tex option is used"),
+            UsageErr(ref message) => message.fmt(f),
+            NoFilenameInputErr(ref path) => {
+                write!(f, "There is no filename to compile in `{}`", path.display())
+            }
+            CannotShowPdfErr(ref viewer) => write!(f, "Cannot show pdf with `{}`", viewer),
+            UnknownEngineErr(ref name) => write!(
+                f,
+                "Unknown TeX engine `{}`, expected one of pdftex, xetex, luatex, tex, plaintex, \
+                 context, pdflatex, xelatex, lualatex, latex or plainlatex",
+                name
+            ),
+            InvalidValueErr {
+                ref key,
+                ref value,
+                expected,
+            } => write!(
+                f,
+                "Invalid value `{}` of {}, expected {}",
+                value, key, expected
+            ),
+            ConfigErr {
+                ref path,
+                line,
+                ref key,
+                ref message,
+            } => {
+                if let Some(path) = path {
+                    write!(f, "{}:", path.display())?;
+                }
+                write!(f, "{}: Invalid config `{}`: {}", line, key, message)
+            }
+            MissingToolErr {
+                ref program,
+                ref step,
+                ref key,
+            } => {
+                write!(f, "Cannot find `{}`", program)?;
+                if let Some(step) = step {
+                    write!(f, " which the {} step needs", step)?;
+                }
+                write!(
+                    f,
+                    ". Install it, or set its path with `{}` in the config file",
+                    key
+                )
+            }
+            CompileErr {
+                ref command,
+                ref exit_code,
+            } => write!(f, "`{}` failed with {}", command, status(exit_code)),
+            BibliographyErr {
+                ref command,
+                ref exit_code,
+            } => write!(
+                f,
+                "Bibliography `{}` failed with {}",
+                command,
+                status(exit_code)
+            ),
+            ToolErr {
+                ref step,
+                ref command,
+                ref exit_code,
+            } => write!(
+                f,
+                "`{}` of the {} step failed with {}",
+                command,
+                step,
+                status(exit_code)
+            ),
+            DoctorErr(1) => write!(f, "1 problem found"),
+            DoctorErr(problems) => write!(f, "{} problems found", problems),
         }
     }
 }
 
-impl From<io::Error> for AutoTeXErr {
-    fn from(err: io::Error) -> Self {
-        Self::IOErr(err)
+impl AutoTeXErr {
+    pub fn file(path: &Path, err: io::Error) -> Self {
+        Self::FileErr(path.to_path_buf(), err)
     }
-}
 
-impl From<ScanError> for AutoTeXErr {
-    fn from(err: ScanError) -> Self {
-        Self::ScanErr(err)
+    pub fn invalid_value(key: &str, value: &str, expected: &'static str) -> Self {
+        Self::InvalidValueErr {
+            key: key.to_string(),
+            value: value.to_string(),
+            expected,
+        }
+    }
+
+    // Tell in which config file the error is
+    pub fn in_file(self, file: &Path) -> Self {
+        match self {
+            Self::ConfigErr {
+                line, key, message, ..
+            } => Self::ConfigErr {
+                path: Some(file.to_path_buf()),
+                line,
+                key,
+                message,
+            },
+            err => err,
+        }
+    }
+
+    // Whether a program of the build failed
+    pub fn is_build_failure(&self) -> bool {
+        matches!(
+            self,
+            Self::CompileErr { .. } | Self::BibliographyErr { .. } | Self::ToolErr { .. }
+        )
+    }
+
+    // Exit code of the process which stopped by this error
+    pub fn exit_code(&self) -> i32 {
+        use AutoTeXErr::*;
+        match self {
+            FileErr(..) => EXIT_IO,
+            CommandErr(e) => e.exit_code(),
+            NoFilenameInputErr(_) | UsageErr(_) => EXIT_USAGE,
+            ScanErr(..) | UnknownEngineErr(_) | InvalidValueErr { .. } | ConfigErr { .. } => {
+                EXIT_CONFIG
+            }
+            MissingToolErr { .. } => EXIT_MISSING_TOOL,
+            CompileErr { .. } => EXIT_COMPILE,
+            BibliographyErr { .. } => EXIT_BIBLIOGRAPHY,
+            ToolErr { .. } => EXIT_TOOL,
+            DoctorErr(_) => EXIT_DOCTOR,
+            SignalErr(_) | CannotShowPdfErr(_) => EXIT_FAILURE,
+        }
     }
 }
 
@@ -49,3 +227,8 @@ impl From<clap::Error> for AutoTeXErr {
 }
 
 pub type Result<T> = std::result::Result<T, AutoTeXErr>;
+
+// The current directory, which fails if it is removed while autotex runs
+pub fn current_dir() -> Result<PathBuf> {
+    env::current_dir().map_err(|err| AutoTeXErr::file(Path::new("."), err))
+}
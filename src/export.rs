@@ -0,0 +1,207 @@
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use crate::error::{self, AutoTeXErr};
+use crate::report::{kind_name, severity_name, Json};
+use crate::tex_log::{Diagnostic, Severity};
+use crate::texfile_info::TeXFileInfo;
+
+// Formats which editors and CI systems read
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DiagnosticsFormat {
+    // file:line:E:message, read by `:set errorformat=%f:%l:%t:%m` in Vim
+    Quickfix,
+    // file:line: error: message, read by the $gcc problem matcher of VS Code
+    Gcc,
+    Sarif,
+    // ::error file=...,line=...::message of GitHub Actions
+    Github,
+}
+
+impl DiagnosticsFormat {
+    pub fn from_name(name: &str) -> error::Result<Self> {
+        match name.to_lowercase().as_str() {
+            "quickfix" => Ok(Self::Quickfix),
+            "gcc" => Ok(Self::Gcc),
+            "sarif" => Ok(Self::Sarif),
+            "github" => Ok(Self::Github),
+            _ => Err(AutoTeXErr::invalid_value(
+                "--diagnostics",
+                name,
+                "one of quickfix, gcc, sarif or github",
+            )),
+        }
+    }
+}
+
+// A diagnostic with a location relative to where autotex runs.
+// Diagnostics without a location point at the first line of the main file.
+struct Located<'a> {
+    file: String,
+    line: usize,
+    diag: &'a Diagnostic,
+}
+
+fn locate<'a>(tex_info: &TeXFileInfo, diag: &'a Diagnostic) -> Located<'a> {
+    let file = diag
+        .file
+        .clone()
+        .unwrap_or_else(|| tex_info.get_main_tex_file());
+    let path = Path::new(&file);
+    let path = if path.is_absolute() {
+        path.to_path_buf()
+    } else {
+        tex_info
+            .current_dir
+            .join(path.strip_prefix(".").unwrap_or(path))
+    };
+    let file = path.strip_prefix(".").unwrap_or(&path).to_string_lossy();
+    Located {
+        file: file.into_owned(),
+        line: diag.line.unwrap_or(1),
+        diag,
+    }
+}
+
+// GitHub Actions reads `%`, CR and LF escaped, and also `:` and `,` in properties
+fn github_escape(s: &str, is_property: bool) -> String {
+    let mut output = s
+        .replace('%', "%25")
+        .replace('\r', "%0D")
+        .replace('\n', "%0A");
+    if is_property {
+        output = output.replace(':', "%3A").replace(',', "%2C");
+    }
+    output
+}
+
+fn sarif(located: &[Located<'_>]) -> Json {
+    let results = located
+        .iter()
+        .map(|loc| {
+            Json::Object(vec![
+                ("ruleId", Json::str(kind_name(loc.diag.kind))),
+                ("level", Json::str(severity_name(loc.diag.severity))),
+                (
+                    "message",
+                    Json::Object(vec![("text", Json::str(&loc.diag.message))]),
+                ),
+                (
+                    "locations",
+                    Json::Array(vec![Json::Object(vec![(
+                        "physicalLocation",
+                        Json::Object(vec![
+                            (
+                                "artifactLocation",
+                                Json::Object(vec![("uri", Json::str(&loc.file))]),
+                            ),
+                            (
+                                "region",
+                                Json::Object(vec![("startLine", Json::Int(loc.line as i64))]),
+                            ),
+                        ]),
+                    )])]),
+                ),
+            ])
+        })
+        .collect();
+
+    Json::Object(vec![
+        (
+            "$schema",
+            Json::str("https://json.schemastore.org/sarif-2.1.0.json"),
+        ),
+        ("version", Json::str("2.1.0")),
+        (
+            "runs",
+            Json::Array(vec![Json::Object(vec![
+                (
+                    "tool",
+                    Json::Object(vec![(
+                        "driver",
+                        Json::Object(vec![
+                            ("name", Json::str("autotex")),
+                            ("version", Json::str(env!("CARGO_PKG_VERSION"))),
+                            (
+                                "informationUri",
+                                Json::str("https://github.com/e0328eric/autotex"),
+                            ),
+                        ]),
+                    )]),
+                ),
+                ("results", Json::Array(results)),
+            ])]),
+        ),
+    ])
+}
+
+// Render diagnostics in the given format
+pub fn render(format: DiagnosticsFormat, tex_info: &TeXFileInfo, diags: &[Diagnostic]) -> String {
+    let located: Vec<Located<'_>> = diags.iter().map(|diag| locate(tex_info, diag)).collect();
+    let mut output = String::new();
+    match format {
+        DiagnosticsFormat::Quickfix => {
+            for loc in located.iter() {
+                let kind = match loc.diag.severity {
+                    Severity::Error => 'E',
+                    Severity::Warning => 'W',
+                };
+                output.push_str(&format!(
+                    "{}:{}:{}:{}\n",
+                    loc.file, loc.line, kind, loc.diag.message
+                ));
+            }
+        }
+        DiagnosticsFormat::Gcc => {
+            for loc in located.iter() {
+                output.push_str(&format!(
+                    "{}:{}: {}: {}\n",
+                    loc.file,
+                    loc.line,
+                    severity_name(loc.diag.severity),
+                    loc.diag.message
+                ));
+            }
+        }
+        DiagnosticsFormat::Sarif => {
+            output = sarif(&located).to_string();
+            output.push('\n');
+        }
+        DiagnosticsFormat::Github => {
+            for loc in located.iter() {
+                output.push_str(&format!(
+                    "::{} file={},line={}::{}\n",
+                    severity_name(loc.diag.severity),
+                    github_escape(&loc.file, true),
+                    loc.line,
+                    github_escape(&loc.diag.message, false)
+                ));
+            }
+        }
+    }
+    output
+}
+
+// Write diagnostics to the file, or to stdout if no file is given.
+// The file is written again after each build.
+pub fn write(
+    format: DiagnosticsFormat,
+    output: Option<&PathBuf>,
+    tex_info: &TeXFileInfo,
+    diags: &[Diagnostic],
+) -> error::Result<()> {
+    let rendered = render(format, tex_info, diags);
+    match output {
+        Some(path) => fs::write(path, rendered).map_err(|err| AutoTeXErr::file(path, err))?,
+        None => {
+            let stdout_error = |err| AutoTeXErr::file(Path::new("<stdout>"), err);
+            let mut stdout = io::stdout();
+            stdout
+                .write_all(rendered.as_bytes())
+                .map_err(stdout_error)?;
+            stdout.flush().map_err(stdout_error)?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,96 @@
+use std::path::{Path, PathBuf};
+
+use crate::bibliography::with_ext;
+use crate::build_state::{self, InputHasher};
+use crate::error;
+use crate::tex_source::{self, read_if_exists};
+use crate::texfile_info::TeXFileInfo;
+
+// Find the options of \glsxtr@resource{options}{basename} entries in the aux file
+fn resource_options(aux: &str) -> Vec<String> {
+    tex_source::find_commands(aux, "glsxtr@resource")
+        .into_iter()
+        .map(|cmd| cmd.argument)
+        .collect()
+}
+
+// Take the bib files given by `src={a,b}` or `src=a` in bib2gls options
+fn resource_sources(options: &str) -> Vec<String> {
+    tex_source::option_value(options, "src")
+        .map(|value| {
+            tex_source::split_list(&value)
+                .map(|name| format!("{}.bib", name.trim_end_matches(".bib")))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+// Take the input extensions of the glossaries declared in the aux file
+// by \@newglossary{type}{log}{out}{in}
+fn glossary_inputs(aux: &str) -> Vec<String> {
+    aux.lines()
+        .filter_map(|line| line.trim().strip_prefix("\\@newglossary"))
+        .filter_map(|rest| {
+            let mut args = rest.split('{').skip(1).map(|arg| arg.split('}').next());
+            args.nth(3).flatten().map(str::to_string)
+        })
+        .collect()
+}
+
+// Take the files of the job which need a glossary tool after the last pass.
+// The tool is chosen from the extension of the returned file:
+//   glo or acn -> makeglossaries, nlo -> makeindex with nomencl.ist,
+//   aux -> bib2gls
+// The files are relative to the directory of the main file.
+pub fn take_files(tex_info: &TeXFileInfo) -> error::Result<Vec<PathBuf>> {
+    let main = &tex_info.jobname;
+    let aux_path = PathBuf::from(with_ext(main, ".aux"));
+    let aux = match read_if_exists(tex_info.in_dir(&aux_path))? {
+        Some(aux) => aux,
+        None => return Ok(vec![]),
+    };
+
+    let mut output = vec![];
+    if !resource_options(&aux).is_empty() {
+        output.push(aux_path.clone());
+    } else if let Some(input) = glossary_inputs(&aux).first() {
+        // makeglossaries processes every glossary at once
+        output.push(PathBuf::from(with_ext(main, &format!(".{}", input))));
+    }
+
+    let nomenclature = PathBuf::from(with_ext(main, ".nlo"));
+    if build_state::written_in_last_pass(
+        &tex_info.in_dir(&nomenclature),
+        &tex_info.in_dir(&aux_path),
+    ) {
+        output.push(nomenclature);
+    }
+    Ok(output)
+}
+
+// Hash the inputs of the glossary tool for the given file in `dir`
+pub fn input_hash(dir: &Path, file: &Path) -> error::Result<u64> {
+    let mut hasher = InputHasher::new();
+    let file = &dir.join(file);
+    let aux = read_if_exists(file.with_extension("aux"))?.unwrap_or_default();
+    match file.extension().and_then(|ext| ext.to_str()) {
+        Some("aux") => {
+            for options in resource_options(&aux) {
+                hasher.write(options.as_bytes());
+                for source in resource_sources(&options) {
+                    hasher.write_file(dir.join(&source))?;
+                }
+            }
+        }
+        Some("nlo") => hasher.write_file(file)?,
+        _ => {
+            for input in glossary_inputs(&aux) {
+                hasher.write_file(file.with_extension(input))?;
+            }
+            for cmd in tex_source::find_commands(&aux, "@istfilename") {
+                hasher.write_file(dir.join(&cmd.argument))?;
+            }
+        }
+    }
+    Ok(hasher.finish())
+}
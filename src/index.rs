@@ -0,0 +1,143 @@
+use std::fs;
+use std::path::PathBuf;
+
+use crate::bibliography::with_ext;
+use crate::build_state;
+use crate::error::{self, AutoTeXErr};
+use crate::tex_source::{self, read_if_exists};
+use crate::texfile_info::TeXFileInfo;
+
+// Programs that make an ind file from an idx file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IndexProcessor {
+    Makeindex,
+    Xindy,
+    Upmendex,
+}
+
+impl IndexProcessor {
+    // Parse the name of a processor given as `key`
+    pub fn from_name(name: &str, key: &str) -> error::Result<Self> {
+        match name.to_lowercase().as_str() {
+            "makeindex" => Ok(Self::Makeindex),
+            "xindy" | "texindy" => Ok(Self::Xindy),
+            "upmendex" => Ok(Self::Upmendex),
+            _ => Err(AutoTeXErr::invalid_value(
+                key,
+                name,
+                "one of makeindex, xindy, texindy or upmendex",
+            )),
+        }
+    }
+
+    pub fn program(&self) -> &'static str {
+        match self {
+            Self::Makeindex => "makeindex",
+            Self::Xindy => "xindy",
+            Self::Upmendex => "upmendex",
+        }
+    }
+
+    // Options which choose a style file
+    pub fn style_args(&self, style: &str) -> [String; 2] {
+        match self {
+            Self::Makeindex | Self::Upmendex => ["-s".to_string(), style.to_string()],
+            Self::Xindy => ["-M".to_string(), style.trim_end_matches(".xdy").to_string()],
+        }
+    }
+}
+
+// An index processor with its options for one idx file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IndexJob {
+    pub file: PathBuf,
+    pub processor: IndexProcessor,
+    pub options: Vec<String>,
+}
+
+// Settings of index processors given by config or magic comments
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IndexSettings {
+    pub processor: IndexProcessor,
+    pub style: Option<String>,
+}
+
+impl Default for IndexSettings {
+    fn default() -> Self {
+        Self {
+            processor: IndexProcessor::Makeindex,
+            style: None,
+        }
+    }
+}
+
+// Take the idx files of the job which were written by the last pass:
+// the main one, the ones of splitidx named like `jobname-suffix.idx`
+// and the ones of imakeidx declared by \makeindex[name=...].
+// Each of them gets its own ind file.
+pub fn take_jobs(tex_info: &TeXFileInfo, settings: &IndexSettings) -> error::Result<Vec<IndexJob>> {
+    let aux_path = tex_info.in_dir(with_ext(&tex_info.jobname, ".aux"));
+    let source = read_if_exists(tex_info.in_dir(tex_info.get_main_tex_file()))?.unwrap_or_default();
+    let source = tex_source::strip_comments(&source);
+
+    // imakeidx can give each index its own program and options
+    let mut declared = vec![];
+    for cmd in tex_source::find_commands(&source, "makeindex") {
+        let options = cmd.optional.first().map(String::as_str).unwrap_or_default();
+        let name = tex_source::option_value(options, "name")
+            .unwrap_or_else(|| tex_info.jobname.to_string_lossy().into_owned());
+        let program = tex_source::option_value(options, "program");
+        let extra = tex_source::option_value(options, "options");
+        declared.push((PathBuf::from(name + ".idx"), program, extra));
+    }
+
+    let prefix = [tex_info.jobname.to_str().unwrap_or_default(), "-"].concat();
+    let mut files = vec![PathBuf::from(with_ext(&tex_info.jobname, ".idx"))];
+    let read_error = |err| AutoTeXErr::file(&tex_info.current_dir, err);
+    for entry in fs::read_dir(&tex_info.current_dir).map_err(read_error)? {
+        let path = PathBuf::from(entry.map_err(read_error)?.file_name());
+        let is_split = path
+            .to_str()
+            .is_some_and(|name| name.starts_with(&prefix) && name.ends_with(".idx"));
+        if is_split && !files.contains(&path) {
+            files.push(path);
+        }
+    }
+    for (file, _, _) in declared.iter() {
+        if !files.contains(file) {
+            files.push(file.clone());
+        }
+    }
+
+    let mut output = vec![];
+    for file in files {
+        if !build_state::written_in_last_pass(&tex_info.in_dir(&file), &aux_path) {
+            continue;
+        }
+        let (program, extra) = declared
+            .iter()
+            .find(|(declared, _, _)| declared == &file)
+            .map(|(_, program, extra)| (program.clone(), extra.clone()))
+            .unwrap_or_default();
+
+        let processor = match program {
+            Some(name) => IndexProcessor::from_name(&name, "program of \\makeindex")?,
+            None => settings.processor,
+        };
+        let mut options = vec![];
+        match extra {
+            Some(extra) => options.extend(extra.split_whitespace().map(str::to_string)),
+            None => {
+                if let Some(style) = &settings.style {
+                    options.extend(processor.style_args(style));
+                }
+            }
+        }
+        output.push(IndexJob {
+            file,
+            processor,
+            options,
+        });
+    }
+    Ok(output)
+}
@@ -0,0 +1,166 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use crate::error;
+use crate::tex_source;
+
+// Editors read magic comments only from the top of a file
+const MAGIC_COMMENT_LINES: usize = 20;
+
+// Magic comments like `% !TEX program = xelatex` at the top of a file.
+// Keys are stored in lowercase since editors write both `!TEX` and `!TeX`.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct MagicComments {
+    values: HashMap<String, String>,
+    // Rules of `% arara: rule` directives in their order
+    arara: Vec<String>,
+}
+
+impl MagicComments {
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.values.get(key).map(String::as_str)
+    }
+
+    // The engine of `% !TEX program` or of `% !TEX TS-program` of TeXShop
+    pub fn program(&self) -> Option<&str> {
+        self.get("program").or_else(|| self.get("ts-program"))
+    }
+
+    pub fn arara(&self) -> &[String] {
+        &self.arara
+    }
+}
+
+// Parse one line like `% !TEX key = value`
+fn parse_line(line: &str) -> Option<(String, String)> {
+    let rest = line.trim_start().strip_prefix('%')?.trim_start();
+    let (magic, rest) = rest.split_at(rest.find(char::is_whitespace)?);
+    if !magic.eq_ignore_ascii_case("!tex") {
+        return None;
+    }
+    let (key, value) = rest.split_once('=')?;
+    Some((key.trim().to_lowercase(), value.trim().to_string()))
+}
+
+// Take the rule of a directive like `% arara: pdflatex: { shell: yes }`.
+// Options and conditions of the directive are not used.
+fn parse_arara(line: &str) -> Option<String> {
+    let rest = line.trim_start().strip_prefix('%')?.trim_start();
+    let rest = rest.strip_prefix("arara:")?.trim_start();
+    let rule = rest
+        .split(|c: char| c == ':' || c.is_whitespace())
+        .next()
+        .filter(|rule| !rule.is_empty())?;
+    Some(rule.to_string())
+}
+
+// Read magic comments of the given file. A missing file has none.
+pub fn read<P: AsRef<Path>>(path: P) -> error::Result<MagicComments> {
+    let source = tex_source::read_if_exists(path)?.unwrap_or_default();
+    Ok(parse(&source))
+}
+
+// arara reads its directives from the whole file
+pub fn parse(source: &str) -> MagicComments {
+    let values = source
+        .lines()
+        .take(MAGIC_COMMENT_LINES)
+        .filter_map(parse_line)
+        .collect();
+    let arara = source.lines().filter_map(parse_arara).collect();
+    MagicComments { values, arara }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::declared_engine;
+
+    #[test]
+    fn keys_ignore_case() {
+        let magic = parse(
+            "% !TeX Program = LuaLaTeX
+%!TEX root=main.tex
+% !TEX spellcheck
+",
+        );
+        assert_eq!(magic.program(), Some("LuaLaTeX"));
+        assert_eq!(magic.get("root"), Some("main.tex"));
+        assert_eq!(magic.get("spellcheck"), None);
+    }
+
+    #[test]
+    fn program_wins_over_ts_program() {
+        let magic = parse(
+            "% !TEX TS-program = pdflatex
+% !TEX program = xelatex
+",
+        );
+        assert_eq!(magic.program(), Some("xelatex"));
+        let magic = parse(
+            "% !TEX TS-program = pdflatex
+",
+        );
+        assert_eq!(magic.program(), Some("pdflatex"));
+    }
+
+    #[test]
+    fn only_the_top_of_the_file_is_read() {
+        let mut source = "\relax
+"
+        .repeat(MAGIC_COMMENT_LINES);
+        source.push_str(
+            "% !TEX program = xelatex
+% arara: lualatex
+",
+        );
+        let magic = parse(&source);
+        assert_eq!(magic.program(), None);
+        assert_eq!(magic.arara(), ["lualatex"]);
+    }
+
+    #[test]
+    fn arara_rules_in_order() {
+        let magic = parse(
+            "% arara: pdflatex: { shell: yes }
+% arara: bibtex
+%arara:makeindex
+",
+        );
+        assert_eq!(magic.arara(), ["pdflatex", "bibtex", "makeindex"]);
+    }
+
+    #[test]
+    fn program_wins_over_arara() {
+        let magic = parse(
+            "% arara: xelatex
+% !TEX program = lualatex
+",
+        );
+        assert_eq!(
+            declared_engine(&magic),
+            Some(("lualatex".to_string(), "% !TEX program"))
+        );
+    }
+
+    #[test]
+    fn arara_engine_if_program_is_not_an_engine() {
+        let magic = parse(
+            "% !TEX program = latexmk
+% arara: bibtex
+% arara: xelatex
+",
+        );
+        assert_eq!(
+            declared_engine(&magic),
+            Some(("xelatex".to_string(), "% arara"))
+        );
+        assert_eq!(
+            declared_engine(&parse(
+                "% arara: bibtex
+"
+            )),
+            None
+        );
+    }
+}
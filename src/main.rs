@@ -1,97 +1,249 @@
 #![warn(rust_2018_idioms)]
+
+// Print a message for humans.
+// It goes to stderr if stdout is reserved for a machine readable report.
+macro_rules! say {
+    ($($arg: tt)*) => {
+        if crate::report::stdout_reserved() {
+            eprintln!($($arg)*)
+        } else {
+            println!($($arg)*)
+        }
+    };
+}
+
+mod bibliography;
+mod build_state;
 mod commands;
 mod compilable;
+mod config;
+mod dependencies;
+mod doctor;
 mod engines;
 mod error;
+mod export;
+mod glossaries;
+mod index;
+mod magic_comments;
+mod packages;
+mod pipeline;
+mod preamble;
+mod profile;
 mod remove_aux;
+mod report;
+mod root;
+mod tex_log;
+mod tex_source;
 mod texfile_info;
+mod tools;
 
 use std::io::ErrorKind;
+use std::path::Path;
 use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
 use std::time::Duration;
-use std::{env, io, thread};
+use std::{process, thread};
 
 use signal_hook::consts::signal::SIGINT;
 use signal_hook::flag as signal_flag;
 
-use crate::commands::AutoTeXCommand;
-use crate::engines::TeXEngine;
+use crate::commands::{AutoTeXCommand, SubCommand};
+use crate::config::Config;
+use crate::engines::{BuildReport, TeXEngine};
+use crate::error::AutoTeXErr;
 use crate::texfile_info::TeXFileInfo;
 
-fn main() -> error::Result<()> {
-    let args = AutoTeXCommand::new()?;
-    run_autotex(args)
+fn main() {
+    let result = AutoTeXCommand::new().and_then(|args| {
+        let diagnostics_to_stdout = args.diagnostics.is_some() && args.diagnostics_output.is_none();
+        if args.report.is_some() || diagnostics_to_stdout {
+            report::reserve_stdout();
+        }
+        for warning in args.config_warnings.iter() {
+            eprintln!("Warning: {}", warning);
+        }
+        run_autotex(args)
+    });
+
+    // A failed build is already shown in its summary
+    if let Err(err) = result {
+        if !err.is_build_failure() {
+            eprintln!("Error: {}", err);
+        }
+        process::exit(err.exit_code());
+    }
+}
+
+// Remove the pdf file of the last build so that a failed build leaves no stale one
+fn remove_pdf(tex_info: &TeXFileInfo) -> error::Result<()> {
+    let pdf_file = tex_info.current_dir.join(tex_info.get_main_pdf_file());
+    match std::fs::remove_file(&pdf_file) {
+        Ok(()) => Ok(()),
+        Err(err) if err.kind() == ErrorKind::NotFound => Ok(()),
+        Err(err) => Err(AutoTeXErr::file(&pdf_file, err)),
+    }
 }
 
-fn run_autotex(args: AutoTeXCommand) -> error::Result<()> {
+fn run_autotex(mut args: AutoTeXCommand) -> error::Result<()> {
+    match args.subcommand {
+        Some(SubCommand::Doctor) => return doctor::run_doctor(),
+        Some(SubCommand::ShowConfig) => {
+            let config = Config::load(&error::current_dir()?)?;
+            for warning in config.warnings.iter() {
+                eprintln!("Warning: {}", warning);
+            }
+            println!("{}", config.show());
+            return Ok(());
+        }
+        None => {}
+    }
     if args.is_remove_aux {
-        return remove_aux::remove_aux(&args.file_path);
+        // The input is a directory, or a file like `main` of the project config
+        // which is cleaned in its directory
+        let dir = if args.file_path.is_dir() {
+            args.file_path.as_path()
+        } else {
+            match args.file_path.parent() {
+                Some(dir) if dir != Path::new("") => dir,
+                _ => Path::new("."),
+            }
+        };
+        return remove_aux::remove_aux(dir);
+    }
+
+    let tex_info = files_info(&args)?;
+    // Only viewing the pdf needs no engine
+    if args.is_view && !args.is_conti_compile {
+        return tex_info.show_pdf(&args.pdf_viewer);
     }
 
-    let tex_info = texfile_info::get_files_info(&args.file_path)?;
-    let engine = engines::take_engine(&args.tex_engine)?;
+    args.apply_magic_comments(&tex_info);
+    args.infer_engine(&tex_info)?;
+    let engine = engines::take_engine(&args)?;
 
     if args.is_conti_compile {
         compile_tex(tex_info, engine, &args)?;
-    } else if !args.is_view {
-        match std::fs::remove_file(tex_info.get_main_pdf_file()).map_err(|err| err.kind()) {
-            Ok(()) | Err(ErrorKind::NotFound) => {}
-            Err(err) => panic!("{}", err),
-        }
-        engine.run_engine(&tex_info)?;
     } else {
-        env::set_current_dir(&tex_info.current_dir)?;
-        tex_info.show_pdf()?
+        remove_pdf(&tex_info)?;
+        if let Some(failure) = build(&engine, &tex_info, &args)?.failure() {
+            return Err(failure);
+        }
     }
 
     Ok(())
 }
 
+// Files of the document, whose output is named by the profile if it gives a name
+fn files_info(args: &AutoTeXCommand) -> error::Result<TeXFileInfo> {
+    let mut tex_info = texfile_info::get_files_info(&args.file_path)?;
+    if let Some(output) = args
+        .profile
+        .as_ref()
+        .and_then(|profile| profile.output.as_ref())
+    {
+        tex_info.jobname = output.into();
+    }
+    Ok(tex_info)
+}
+
+// Install packages which the failed build could not find, and retry it once
+fn retry_with_packages(
+    engine: &TeXEngine<String>,
+    tex_info: &TeXFileInfo,
+    args: &AutoTeXCommand,
+    build_report: &mut BuildReport,
+) -> error::Result<()> {
+    let missing = packages::missing_packages(&build_report.diagnostics);
+    if build_report.success || missing.is_empty() {
+        return Ok(());
+    }
+    let installer = match &args.installer {
+        Some(installer) => installer,
+        None => {
+            for package in missing.iter() {
+                say!(
+                    "Missing `{}`. Set `install.command` in the config file to install it.",
+                    package.file
+                );
+            }
+            return Ok(());
+        }
+    };
+    if !installer.install(&missing, &args.tools)? {
+        return Ok(());
+    }
+    say!("Retry the build with the installed packages.");
+    remove_pdf(tex_info)?;
+    *build_report = BuildReport::new();
+    engine.run_engine(tex_info, build_report)
+}
+
+// Compile once, and write a report and diagnostics of it if they are asked
+fn build(
+    engine: &TeXEngine<String>,
+    tex_info: &TeXFileInfo,
+    args: &AutoTeXCommand,
+) -> error::Result<BuildReport> {
+    let mut build_report = BuildReport::new();
+    let result = engine
+        .run_engine(tex_info, &mut build_report)
+        .and_then(|()| retry_with_packages(engine, tex_info, args, &mut build_report));
+
+    // A build stopped by an error, like a missing tool, is reported as well
+    if let Some(format) = args.report {
+        let error = result.as_ref().err();
+        report::emit(format, &args.tex_engine, tex_info, &build_report, error);
+    }
+    let exported = match args.diagnostics {
+        Some(format) => export::write(
+            format,
+            args.diagnostics_output.as_ref(),
+            tex_info,
+            &build_report.diagnostics,
+        ),
+        None => Ok(()),
+    };
+    result?;
+    exported?;
+    Ok(build_report)
+}
+
 fn compile_tex(
     mut tex_info: TeXFileInfo,
     engine: TeXEngine<String>,
     args: &AutoTeXCommand,
 ) -> error::Result<()> {
-    // First, collect the modification time for each files
-    // in the current directory and its children.
-    let mut init_time = tex_info.take_time()?;
+    // First, collect the modification time for each file
+    // which the document reads.
+    let mut init_time = tex_info.take_time();
 
-    // Then change the directory to compile.
-    let curr_dir = env::current_dir()?;
     let trap = Arc::new(AtomicUsize::new(0));
-    signal_flag::register_usize(SIGINT, Arc::clone(&trap), SIGINT as usize)?;
+    signal_flag::register_usize(SIGINT, Arc::clone(&trap), SIGINT as usize)
+        .map_err(AutoTeXErr::SignalErr)?;
 
     // If it has an error while compile first, then exit whole program.
-    let has_error_first = engine.run_engine(&tex_info)?;
-    if !has_error_first {
-        return Ok(());
+    if let Some(failure) = build(&engine, &tex_info, args)?.failure() {
+        return Err(failure);
     }
     if args.is_view {
-        tex_info.show_pdf()?;
+        tex_info.show_pdf(&args.pdf_viewer)?;
     }
 
     // If not, then show a pdf file if the view option is used
     thread::sleep(Duration::from_secs(1));
-    env::set_current_dir(&curr_dir)?;
-    println!("Press Ctrl+C to finish the program.");
+    say!("Press Ctrl+C to finish the program.");
     while trap.load(Ordering::Relaxed) != SIGINT as usize {
-        let compare_time = tex_info.take_time()?;
+        let compare_time = tex_info.take_time();
         if init_time != compare_time {
-            tex_info = texfile_info::get_files_info(&args.file_path)?;
-            match std::fs::remove_file(tex_info.get_main_pdf_file()) {
-                Ok(()) => {}
-                Err(err) if err.kind() == io::ErrorKind::NotFound => {}
-                Err(err) => return Err(error::AutoTeXErr::IOErr(err)),
-            }
-            engine.run_engine(&tex_info)?;
-            env::set_current_dir(&curr_dir)?;
-            init_time = tex_info.take_time()?;
-            println!("Press Ctrl+C to finish the program.");
+            tex_info = files_info(args)?;
+            remove_pdf(&tex_info)?;
+            build(&engine, &tex_info, args)?;
+            init_time = tex_info.take_time();
+            say!("Press Ctrl+C to finish the program.");
         }
         thread::sleep(Duration::from_secs(1));
     }
-    println!("\nQuitting");
+    say!("\nQuitting");
 
     Ok(())
 }
@@ -0,0 +1,164 @@
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+use crate::compilable::{Compilable, Invocation};
+use crate::config::{Node, Value};
+use crate::error;
+use crate::tex_log::{Diagnostic, DiagnosticKind};
+use crate::tools::{self, ToolPaths};
+
+// A package, a class or a font which the engine could not find
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MissingPackage {
+    // Name of the file without its extension, like `siunitx`.
+    // It is only a guess of the package, since `tikz.sty` is in `pgf`.
+    pub name: String,
+    // File which was not found, like `siunitx.sty`
+    pub file: String,
+}
+
+// Text between the quotes of `File `foo.sty' not found`
+fn quoted_file(message: &str) -> Option<&str> {
+    let rest = message.split("File `").nth(1)?;
+    let (file, rest) = rest.split_once('\'')?;
+    rest.trim_start().starts_with("not found").then_some(file)
+}
+
+// Read a missing package from messages like
+//   ! LaTeX Error: File `siunitx.sty' not found.
+//   ! Font \T1/cmr/m/n/10=ecrm1000 at 10.0pt not loadable: Metric (TFM) file not found.
+//   ! Package fontspec Error: The font "Foo Sans" cannot be found.
+fn missing_package(diag: &Diagnostic) -> Option<MissingPackage> {
+    let message = &diag.message;
+    if let Some(file) = quoted_file(message) {
+        let is_package = [".sty", ".cls"].iter().any(|ext| file.ends_with(ext));
+        return is_package.then(|| MissingPackage {
+            name: file[..file.len() - 4].to_string(),
+            file: file.to_string(),
+        });
+    }
+    if message.contains("Metric (TFM) file not found") {
+        let font = message.split('=').nth(1)?.split_whitespace().next()?;
+        return Some(MissingPackage {
+            name: font.to_string(),
+            file: format!("{}.tfm", font),
+        });
+    }
+    if message.contains("fontspec Error") && message.contains("cannot be found") {
+        let font = message.split('"').nth(1)?;
+        return Some(MissingPackage {
+            name: font.to_string(),
+            file: font.to_string(),
+        });
+    }
+    None
+}
+
+// Missing packages in the errors of a build, without duplicates
+pub fn missing_packages(diags: &[Diagnostic]) -> Vec<MissingPackage> {
+    let mut output: Vec<MissingPackage> = vec![];
+    let candidates = diags
+        .iter()
+        .filter(|diag| {
+            matches!(
+                diag.kind,
+                DiagnosticKind::MissingFile | DiagnosticKind::Font
+            )
+        })
+        .filter_map(missing_package);
+    for package in candidates {
+        if !output.contains(&package) {
+            output.push(package);
+        }
+    }
+    output
+}
+
+// Find the TeX Live package which has the file from the output of
+// `tlmgr search --global --file /tikz.sty`, which is like
+//   tlmgr: package repository https://mirror.ctan.org/systems/texlive/tlnet (verified)
+//   pgf:
+//           texmf-dist/tex/latex/pgf/frontendlayer/tikz.sty
+fn owning_package(file: &str, tools: &ToolPaths) -> Option<String> {
+    let tlmgr = tools::find_program(tools.get("tlmgr"))?;
+    let output = Command::new(tlmgr)
+        .args(["search", "--global", "--file", &format!("/{}", file)])
+        .stdin(Stdio::null())
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter(|line| !line.starts_with(char::is_whitespace) && !line.starts_with("tlmgr"))
+        .find_map(|line| line.trim().strip_suffix(':').map(str::to_string))
+}
+
+// A command which installs a missing package, given by the config like
+//   install:
+//     command: tlmgr install {pkg}
+// `{pkg}` is replaced by the package which has the missing file and `{file}` by the file.
+// The package is searched with tlmgr, and the name of the file is used without it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Installer {
+    pub command: Vec<String>,
+}
+
+impl Installer {
+    pub fn from_config(node: &Node) -> error::Result<Self> {
+        let command = match node.get("command") {
+            Some(command) => match &command.value {
+                Value::Str(command) => command.split_whitespace().map(str::to_string).collect(),
+                _ => command.as_string_list()?,
+            },
+            None => vec![],
+        };
+        if command.is_empty() {
+            return Err(node.expected("`command` like `tlmgr install {pkg}`"));
+        }
+        Ok(Self { command })
+    }
+
+    // Install every package, and return whether any of them was installed
+    pub fn install(&self, packages: &[MissingPackage], tools: &ToolPaths) -> error::Result<bool> {
+        let program = tools.require(&self.command[0], "install")?;
+        let mut installed = false;
+        let mut names: Vec<String> = vec![];
+        for package in packages {
+            // A font name of fontspec is not a file to search
+            let owner = Path::new(&package.file)
+                .extension()
+                .and_then(|_| owning_package(&package.file, tools));
+            let name = match owner {
+                Some(owner) => {
+                    if owner != package.name {
+                        say!("`{}` is in the package `{}`", package.file, owner);
+                    }
+                    owner
+                }
+                None => package.name.clone(),
+            };
+            if names.contains(&name) {
+                continue;
+            }
+            let args: Vec<String> = self.command[1..]
+                .iter()
+                .map(|arg| arg.replace("{pkg}", &name).replace("{file}", &package.file))
+                .collect();
+            say!("Install the missing package `{}`", name);
+            let outcome = program.compile(&Invocation::new(args))?;
+            if outcome.success {
+                installed = true;
+            } else {
+                say!(
+                    "`{}` failed, so `{}` is not installed.",
+                    outcome.command_line,
+                    name
+                );
+            }
+            names.push(name);
+        }
+        Ok(installed)
+    }
+}
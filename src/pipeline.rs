@@ -0,0 +1,608 @@
+use std::fmt;
+use std::path::Path;
+
+use crate::bibliography::{self, with_ext, BibBackend};
+use crate::build_state::{self, BuildState, InputHasher};
+use crate::commands::{is_engine, AutoTeXCommand};
+use crate::compilable::{Compilable, Invocation};
+use crate::config::{optional_string, string_list, Node};
+use crate::engines::BuildReport;
+use crate::error::{self, AutoTeXErr};
+use crate::glossaries;
+use crate::index::{self, IndexProcessor, IndexSettings};
+use crate::profile::Profile;
+use crate::texfile_info::TeXFileInfo;
+use crate::tools::ToolPaths;
+
+// Names of the built-in steps in their default order
+pub const DEFAULT_STEPS: [&str; 4] = ["bibliography", "index", "glossaries", "asymptote"];
+
+// Built-in step which runs only if it is listed in the steps
+const PDFA_STEP: &str = "pdfa";
+
+fn is_builtin(name: &str) -> bool {
+    DEFAULT_STEPS.contains(&name) || name == PDFA_STEP
+}
+
+// A command run by a step, with what decides whether it can be skipped
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Job {
+    pub key: String,
+    pub program: String,
+    pub args: Vec<String>,
+    // A job without inputs to hash always runs
+    pub input_hash: Option<u64>,
+    pub output_exists: bool,
+}
+
+impl Job {
+    fn new(key: String, program: &str, args: Vec<String>) -> Self {
+        Self {
+            key,
+            program: program.to_string(),
+            args,
+            input_hash: None,
+            output_exists: false,
+        }
+    }
+
+    // Let the job be skipped if its inputs are unchanged and its output exists
+    fn skip_if_unchanged(mut self, input_hash: u64, output_exists: bool) -> Self {
+        self.input_hash = Some(input_hash);
+        self.output_exists = output_exists;
+        self
+    }
+}
+
+// What a step can see after a pass of the engine
+pub struct BuildContext<'a> {
+    pub tex_info: &'a TeXFileInfo,
+    pub is_tex: bool,
+    // Number of passes of the engine so far
+    pub passes: usize,
+    // Hashes of the tools, loaded once for the whole build
+    pub state: BuildState,
+}
+
+// A step of the build pipeline which runs after a pass of the engine
+pub trait Step: fmt::Debug {
+    fn name(&self) -> &str;
+
+    // Take the jobs which the last pass asked for.
+    // Their programs run in the directory of the main file.
+    fn jobs(&self, ctx: &BuildContext<'_>) -> error::Result<Vec<Job>>;
+
+    // Whether the engine must run again after a job of this step ran
+    fn reruns_engine(&self) -> bool {
+        true
+    }
+
+    // Whether the step checks the output after the last pass
+    // instead of running after the first one
+    fn checks_output(&self) -> bool {
+        false
+    }
+}
+
+fn path_string(path: &Path) -> String {
+    path.to_string_lossy().into_owned()
+}
+
+fn file_hash(path: &Path) -> error::Result<u64> {
+    let mut hasher = InputHasher::new();
+    hasher.write_file(path)?;
+    Ok(hasher.finish())
+}
+
+// bibtex or biber
+#[derive(Debug)]
+struct BibliographyStep {
+    backend: Option<BibBackend>,
+}
+
+impl Step for BibliographyStep {
+    fn name(&self) -> &str {
+        "bibliography"
+    }
+
+    fn jobs(&self, ctx: &BuildContext<'_>) -> error::Result<Vec<Job>> {
+        if ctx.is_tex {
+            return Ok(vec![]);
+        }
+        let dir = &ctx.tex_info.current_dir;
+        let mut output = vec![];
+        for job in bibliography::take_jobs(ctx.tex_info, self.backend)? {
+            let target = job.target.to_string_lossy().into_owned();
+            output.push(
+                Job::new(job.state_key(), job.backend.program(), vec![target])
+                    .skip_if_unchanged(job.input_hash(dir)?, job.output_exists(dir)),
+            );
+        }
+        Ok(output)
+    }
+}
+
+// makeindex, xindy or upmendex
+#[derive(Debug)]
+struct IndexStep {
+    settings: IndexSettings,
+}
+
+impl IndexStep {
+    // Magic comments of the document win over the config
+    fn settings(&self, tex_info: &TeXFileInfo) -> error::Result<IndexSettings> {
+        let magic = &tex_info.magic_comments;
+        let processor = match magic.get("index-processor") {
+            Some(name) => IndexProcessor::from_name(name, "% !TEX index-processor")?,
+            None => self.settings.processor,
+        };
+        let style = magic
+            .get("index-style")
+            .map(str::to_string)
+            .or_else(|| self.settings.style.clone());
+        Ok(IndexSettings { processor, style })
+    }
+}
+
+impl Step for IndexStep {
+    fn name(&self) -> &str {
+        "index"
+    }
+
+    fn jobs(&self, ctx: &BuildContext<'_>) -> error::Result<Vec<Job>> {
+        if ctx.is_tex {
+            return Ok(vec![]);
+        }
+        let settings = self.settings(ctx.tex_info)?;
+        let mut output = vec![];
+        for job in index::take_jobs(ctx.tex_info, &settings)? {
+            let ind = job.file.with_extension("ind");
+            let mut args = job.options;
+            args.extend(["-o".to_string(), path_string(&ind), path_string(&job.file)]);
+            output.push(
+                Job::new(path_string(&job.file), job.processor.program(), args).skip_if_unchanged(
+                    file_hash(&ctx.tex_info.in_dir(&job.file))?,
+                    ctx.tex_info.in_dir(&ind).exists(),
+                ),
+            );
+        }
+        Ok(output)
+    }
+}
+
+// makeglossaries, bib2gls or makeindex for nomencl
+#[derive(Debug)]
+struct GlossariesStep;
+
+impl Step for GlossariesStep {
+    fn name(&self) -> &str {
+        "glossaries"
+    }
+
+    fn jobs(&self, ctx: &BuildContext<'_>) -> error::Result<Vec<Job>> {
+        if ctx.is_tex {
+            return Ok(vec![]);
+        }
+        let mut output = vec![];
+        for file in glossaries::take_files(ctx.tex_info)? {
+            let stem = path_string(&file.with_extension(""));
+            let (job, result) = match file.extension().and_then(|ext| ext.to_str()) {
+                Some("aux") => (
+                    Job::new(path_string(&file), "bib2gls", vec![stem]),
+                    "glstex",
+                ),
+                Some("nlo") => {
+                    let nls = path_string(&file.with_extension("nls"));
+                    let args = ["-s", "nomencl.ist", "-o", &nls, &path_string(&file)]
+                        .iter()
+                        .map(|arg| arg.to_string())
+                        .collect();
+                    (Job::new(path_string(&file), "makeindex", args), "nls")
+                }
+                Some("acn") => (
+                    Job::new(path_string(&file), "makeglossaries", vec![stem]),
+                    "acr",
+                ),
+                _ => (
+                    Job::new(path_string(&file), "makeglossaries", vec![stem]),
+                    "gls",
+                ),
+            };
+            let output_exists = ctx.tex_info.in_dir(file.with_extension(result)).exists();
+            let input_hash = glossaries::input_hash(&ctx.tex_info.current_dir, &file)?;
+            output.push(job.skip_if_unchanged(input_hash, output_exists));
+        }
+        Ok(output)
+    }
+}
+
+// asy on the figures generated by the asymptote package
+#[derive(Debug)]
+struct AsymptoteStep;
+
+// Output formats of asy
+const ASY_OUTPUTS: [&str; 5] = ["pdf", "eps", "png", "svg", "tex"];
+
+impl Step for AsymptoteStep {
+    fn name(&self) -> &str {
+        "asymptote"
+    }
+
+    fn jobs(&self, ctx: &BuildContext<'_>) -> error::Result<Vec<Job>> {
+        // A package which loads asymptote is not read, but the asy files
+        // which the last pass wrote still tell that it is used
+        let aux = ctx.tex_info.in_dir(with_ext(&ctx.tex_info.jobname, ".aux"));
+        let generated = ctx
+            .tex_info
+            .generated_asy_files()?
+            .into_iter()
+            .filter(|file| {
+                ctx.tex_info.asymptote_exists
+                    || build_state::written_in_last_pass(&ctx.tex_info.in_dir(file), &aux)
+            });
+        let mut output = vec![];
+        for file in generated {
+            let output_exists = ASY_OUTPUTS
+                .iter()
+                .any(|ext| ctx.tex_info.in_dir(file.with_extension(ext)).exists());
+            output.push(
+                Job::new(path_string(&file), "asy", vec![path_string(&file)])
+                    .skip_if_unchanged(file_hash(&ctx.tex_info.in_dir(&file))?, output_exists),
+            );
+        }
+        Ok(output)
+    }
+}
+
+// A check of the pdf file against PDF/A, given in the config like
+//   pipeline:
+//     pdfa:
+//       command: verapdf
+//       args: ["--flavour", "2b", "{jobname}.pdf"]
+// It runs after the last pass, and the build fails if the verifier fails.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PdfaStep {
+    pub command: String,
+    pub args: Vec<String>,
+}
+
+impl Default for PdfaStep {
+    fn default() -> Self {
+        Self {
+            command: "verapdf".to_string(),
+            args: vec!["{jobname}.pdf".to_string()],
+        }
+    }
+}
+
+impl Step for PdfaStep {
+    fn name(&self) -> &str {
+        PDFA_STEP
+    }
+
+    fn jobs(&self, ctx: &BuildContext<'_>) -> error::Result<Vec<Job>> {
+        // A failed pass or a dvi engine leaves no pdf file to check
+        let pdf = ctx.tex_info.get_main_pdf_file();
+        if !ctx.tex_info.in_dir(&pdf).exists() {
+            return Ok(vec![]);
+        }
+        let jobname = ctx.tex_info.jobname.to_string_lossy();
+        let args = self
+            .args
+            .iter()
+            .map(|arg| arg.replace("{jobname}", &jobname))
+            .collect();
+        Ok(vec![Job::new(pdf, &self.command, args)])
+    }
+
+    fn reruns_engine(&self) -> bool {
+        false
+    }
+
+    fn checks_output(&self) -> bool {
+        true
+    }
+}
+
+impl PdfaStep {
+    fn from_config(node: &Node) -> error::Result<Self> {
+        let default = Self::default();
+        Ok(Self {
+            command: optional_string(node.get("command"))?.unwrap_or(default.command),
+            args: match node.get("args") {
+                Some(args) => args.as_string_list()?,
+                None => default.args,
+            },
+        })
+    }
+}
+
+// A step declared in the config file.
+// `{jobname}` in its fields is replaced by the name of the output files,
+// which is the name of the main file without its extension by default.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CommandStep {
+    pub name: String,
+    pub command: String,
+    pub args: Vec<String>,
+    // The step runs only if the last pass wrote this file
+    pub trigger: Option<String>,
+    pub inputs: Vec<String>,
+    pub outputs: Vec<String>,
+    pub rerun: bool,
+}
+
+impl Step for CommandStep {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn jobs(&self, ctx: &BuildContext<'_>) -> error::Result<Vec<Job>> {
+        let jobname = ctx.tex_info.jobname.to_string_lossy();
+        let expand = |field: &String| field.replace("{jobname}", &jobname);
+
+        match &self.trigger {
+            Some(trigger) => {
+                let aux = ctx.tex_info.in_dir(with_ext(&ctx.tex_info.jobname, ".aux"));
+                if !build_state::written_in_last_pass(&ctx.tex_info.in_dir(expand(trigger)), &aux) {
+                    return Ok(vec![]);
+                }
+            }
+            // Without a trigger or inputs, nothing tells that the step must run again
+            None if self.inputs.is_empty() && ctx.passes > 1 => return Ok(vec![]),
+            None => {}
+        }
+
+        let mut job = Job::new(
+            self.name.clone(),
+            &expand(&self.command),
+            self.args.iter().map(expand).collect(),
+        );
+        if !self.inputs.is_empty() {
+            let mut hasher = InputHasher::new();
+            for input in self.inputs.iter().map(expand) {
+                hasher.write_file(ctx.tex_info.in_dir(input))?;
+            }
+            let output_exists = self
+                .outputs
+                .iter()
+                .all(|output| ctx.tex_info.in_dir(expand(output)).exists());
+            job = job.skip_if_unchanged(hasher.finish(), output_exists);
+        }
+        Ok(vec![job])
+    }
+
+    fn reruns_engine(&self) -> bool {
+        self.rerun
+    }
+}
+
+impl CommandStep {
+    fn from_config(node: &Node) -> error::Result<Self> {
+        let required = |field: &str| match node.get(field) {
+            Some(value) => value.as_string(),
+            None => Err(node.error(format!("`{}` of the step is missing", field))),
+        };
+        let name = required("name")?;
+        let command = required("command")?;
+        let rerun = match node.get("rerun") {
+            Some(rerun) => rerun.as_bool()?,
+            None => true,
+        };
+        Ok(Self {
+            args: string_list(node.get("args"))?,
+            trigger: optional_string(node.get("trigger"))?,
+            inputs: string_list(node.get("inputs"))?,
+            outputs: string_list(node.get("outputs"))?,
+            name,
+            command,
+            rerun,
+        })
+    }
+}
+
+// The `pipeline` part of the config file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PipelineConfig {
+    pub steps: Vec<String>,
+    pub disabled: Vec<String>,
+    pub custom: Vec<CommandStep>,
+    pub pdfa: PdfaStep,
+}
+
+impl Default for PipelineConfig {
+    fn default() -> Self {
+        Self {
+            steps: DEFAULT_STEPS.iter().map(|x| x.to_string()).collect(),
+            disabled: vec![],
+            custom: vec![],
+            pdfa: PdfaStep::default(),
+        }
+    }
+}
+
+impl PipelineConfig {
+    // If `steps` is not given, custom steps run after the built-in ones
+    pub fn from_config(node: &Node) -> error::Result<Self> {
+        let mut custom = vec![];
+        if let Some(items) = node.get("custom") {
+            for item in items.as_list()? {
+                custom.push(CommandStep::from_config(item)?);
+            }
+        }
+
+        let steps = match node.get("steps") {
+            Some(steps) => {
+                let names = steps.as_string_list()?;
+                let is_known = |name: &String| {
+                    is_builtin(name) || custom.iter().any(|step| &step.name == name)
+                };
+                if let Some(name) = names.iter().find(|name| !is_known(name)) {
+                    return Err(steps.error(format!(
+                        "unknown step `{}`, expected a built-in step or a name of pipeline.custom",
+                        name
+                    )));
+                }
+                names
+            }
+            None => DEFAULT_STEPS
+                .iter()
+                .map(|x| x.to_string())
+                .chain(custom.iter().map(|step| step.name.clone()))
+                .collect(),
+        };
+
+        Ok(Self {
+            steps,
+            disabled: string_list(node.get("disable"))?,
+            custom,
+            pdfa: match node.get("pdfa") {
+                Some(pdfa) => PdfaStep::from_config(pdfa)?,
+                None => PdfaStep::default(),
+            },
+        })
+    }
+}
+
+// Steps declared by `% arara:` directives, in their order.
+// Rules of engines are left out since the engine runs by itself,
+// and rules which are neither a tool nor a custom step are ignored.
+pub fn arara_steps(rules: &[String], config: &PipelineConfig) -> Vec<String> {
+    let mut steps: Vec<String> = vec![];
+    for rule in rules {
+        let step = match rule.as_str() {
+            "bibtex" | "biber" => "bibliography",
+            "makeindex" | "xindy" | "texindy" | "upmendex" => "index",
+            "makeglossaries" | "bib2gls" | "nomencl" => "glossaries",
+            "asymptote" | "asy" => "asymptote",
+            name if is_engine(name) => continue,
+            name if config.custom.iter().any(|step| step.name == name) => name,
+            name => {
+                say!("Ignore `% arara: {}`, which is not a step of autotex", name);
+                continue;
+            }
+        };
+        if !steps.iter().any(|other| other == step) {
+            steps.push(step.to_string());
+        }
+    }
+    steps
+}
+
+// Result of running every step once
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PipelineOutcome {
+    Failed,
+    Finished { needs_rerun: bool },
+}
+
+// Steps which run in order after the first pass of the engine
+#[derive(Debug)]
+pub struct Pipeline {
+    steps: Vec<Box<dyn Step>>,
+    tools: ToolPaths,
+}
+
+impl Pipeline {
+    pub fn new(args: &AutoTeXCommand) -> error::Result<Self> {
+        let config = &args.pipeline;
+        // A profile can choose other steps
+        let (names, key) = match &args.profile {
+            Some(
+                profile @ Profile {
+                    steps: Some(steps), ..
+                },
+            ) => (steps, profile.steps_key()),
+            _ => (&config.steps, "pipeline.steps".to_string()),
+        };
+        let mut steps: Vec<Box<dyn Step>> = vec![];
+        for name in names.iter() {
+            if config.disabled.contains(name) {
+                continue;
+            }
+            let step: Box<dyn Step> = match name.as_str() {
+                "bibliography" => Box::new(BibliographyStep {
+                    backend: args.bib_backend,
+                }),
+                "index" => Box::new(IndexStep {
+                    settings: args.index_settings.clone(),
+                }),
+                "glossaries" => Box::new(GlossariesStep),
+                "asymptote" => Box::new(AsymptoteStep),
+                PDFA_STEP => Box::new(config.pdfa.clone()),
+                _ => match config.custom.iter().find(|step| &step.name == name) {
+                    Some(step) => Box::new(step.clone()),
+                    None => {
+                        return Err(AutoTeXErr::invalid_value(
+                            &key,
+                            name,
+                            "a built-in step or a name of pipeline.custom",
+                        ))
+                    }
+                },
+            };
+            steps.push(step);
+        }
+        Ok(Self {
+            steps,
+            tools: args.tools.clone(),
+        })
+    }
+
+    // Run every step once. A job runs only if its inputs changed
+    // since its last successful run.
+    pub fn run(
+        &self,
+        ctx: &mut BuildContext<'_>,
+        report: &mut BuildReport,
+    ) -> error::Result<PipelineOutcome> {
+        self.run_steps(ctx, report, false)
+    }
+
+    // Run the steps which check the output of the last pass
+    pub fn check(&self, ctx: &mut BuildContext<'_>, report: &mut BuildReport) -> error::Result<()> {
+        self.run_steps(ctx, report, true)?;
+        Ok(())
+    }
+
+    fn run_steps(
+        &self,
+        ctx: &mut BuildContext<'_>,
+        report: &mut BuildReport,
+        checks: bool,
+    ) -> error::Result<PipelineOutcome> {
+        let mut needs_rerun = false;
+        let mut outcome = PipelineOutcome::Finished { needs_rerun };
+
+        let steps = self
+            .steps
+            .iter()
+            .filter(|step| step.checks_output() == checks);
+        'steps: for step in steps {
+            for job in step.jobs(ctx)? {
+                if let Some(hash) = job.input_hash {
+                    if job.output_exists && ctx.state.is_unchanged(&job.key, hash) {
+                        say!("Skip {}: its inputs are unchanged.", job.key);
+                        continue;
+                    }
+                }
+                let program = self.tools.require(&job.program, step.name())?;
+                say!("Run the {} step: {}", step.name(), program);
+                let mut invocation = Invocation::new(&job.args);
+                invocation.current_dir = Some(ctx.tex_info.current_dir.clone());
+                let result = program.compile(&invocation)?;
+                if !report.record(step.name(), result) {
+                    outcome = PipelineOutcome::Failed;
+                    break 'steps;
+                }
+                if let Some(hash) = job.input_hash {
+                    ctx.state.record(&job.key, hash);
+                }
+                needs_rerun = needs_rerun || step.reruns_engine();
+                outcome = PipelineOutcome::Finished { needs_rerun };
+            }
+        }
+
+        Ok(outcome)
+    }
+}
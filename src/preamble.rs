@@ -0,0 +1,93 @@
+use std::path::Path;
+
+use crate::dependencies;
+use crate::error;
+use crate::tex_source::{self, TeXCommand};
+use crate::texfile_info::TeXFileInfo;
+
+// Packages which work only with luatex
+const LUA_PACKAGES: [&str; 5] = [
+    "luacode",
+    "luatexja",
+    "luatexja-fontspec",
+    "luamplib",
+    "lualatex-math",
+];
+
+// Packages which need xetex or luatex. xetex is chosen for them.
+const UNICODE_PACKAGES: [&str; 4] = ["fontspec", "unicode-math", "polyglossia", "xeCJK"];
+
+// The engine chosen from the source, and why it is chosen
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Inference {
+    pub engine: &'static str,
+    pub reason: String,
+}
+
+// Packages loaded by \usepackage or \RequirePackage
+fn packages(source: &str) -> Vec<String> {
+    tex_source::find_commands(source, "usepackage")
+        .iter()
+        .chain(tex_source::find_commands(source, "RequirePackage").iter())
+        .flat_map(|cmd: &TeXCommand| {
+            tex_source::split_list(&cmd.argument)
+                .map(str::to_string)
+                .collect::<Vec<_>>()
+        })
+        .collect()
+}
+
+// The main file and the local files which it reads directly,
+// since the preamble is often kept in a file of its own
+fn read_sources(tex_info: &TeXFileInfo) -> error::Result<String> {
+    let dir = &tex_info.current_dir;
+    let main = tex_source::read_if_exists(dir.join(tex_info.get_main_tex_file()))?;
+    let main = tex_source::strip_comments(&main.unwrap_or_default());
+
+    let mut output = main.clone();
+    let local_packages = packages(&main)
+        .into_iter()
+        .map(|name| Path::new(&name).with_extension("sty"));
+    for file in dependencies::included_files(&main)
+        .into_iter()
+        .chain(local_packages)
+    {
+        if let Some(source) = tex_source::read_if_exists(dir.join(file))? {
+            output.push_str(&tex_source::strip_comments(&source));
+        }
+    }
+    Ok(output)
+}
+
+// Choose an engine from what the document uses
+pub fn infer_engine(tex_info: &TeXFileInfo) -> error::Result<Inference> {
+    let source = read_sources(tex_info)?;
+    let packages = packages(&source);
+    let uses = |names: &[&str]| {
+        names
+            .iter()
+            .find(|name| packages.iter().any(|package| package == *name))
+            .map(|name| name.to_string())
+    };
+    let inference = |engine, reason: String| Ok(Inference { engine, reason });
+
+    if tex_source::has_command(&source, "starttext") {
+        return inference("context", "it uses \\starttext of ConTeXt".to_string());
+    }
+    let is_latex = tex_source::has_command(&source, "documentclass");
+    let lua = uses(&LUA_PACKAGES).or_else(|| {
+        tex_source::has_command(&source, "directlua").then(|| "\\directlua".to_string())
+    });
+    let unicode = uses(&UNICODE_PACKAGES);
+
+    match (is_latex, lua, unicode) {
+        (true, Some(name), _) => inference("lualatex", format!("it uses {}", name)),
+        (true, None, Some(name)) => inference("xelatex", format!("it uses {}", name)),
+        (true, None, None) => inference("pdflatex", "it has \\documentclass".to_string()),
+        (false, Some(name), _) => inference(
+            "luatex",
+            format!("it uses {} without \\documentclass", name),
+        ),
+        (false, None, _) => inference("pdftex", "it has no \\documentclass".to_string()),
+    }
+}
@@ -0,0 +1,46 @@
+use crate::config::{optional_string, string_list, Node, Value};
+use crate::error;
+
+// A named way to build the document, given in the config like
+//   profiles:
+//     draft:
+//       max_runs: 1
+//       preamble: \def\isdraft{}
+//       output: thesis-draft
+// and selected with `--profile draft`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Profile {
+    pub name: String,
+    pub engine: Option<String>,
+    pub max_runs: Option<usize>,
+    // Options of the engine after the ones of the config
+    pub flags: Vec<String>,
+    // Steps of the pipeline instead of `pipeline.steps`
+    pub steps: Option<Vec<String>>,
+    // Name of the output files instead of the name of the main file
+    pub output: Option<String>,
+    // TeX code which runs before the main file
+    pub preamble: Option<String>,
+}
+
+impl Profile {
+    pub fn from_config(name: &str, node: &Node) -> error::Result<Self> {
+        if !matches!(node.value, Value::Map(_)) {
+            return Err(node.expected("a map of settings"));
+        }
+        Ok(Self {
+            name: name.to_string(),
+            engine: node.get("engine").map(Node::as_engine).transpose()?,
+            max_runs: node.get("max_runs").map(Node::as_positive).transpose()?,
+            flags: string_list(node.get("flags"))?,
+            steps: node.get("steps").map(Node::as_string_list).transpose()?,
+            output: optional_string(node.get("output"))?,
+            preamble: optional_string(node.get("preamble"))?,
+        })
+    }
+
+    // Where the steps of the pipeline come from, for messages
+    pub fn steps_key(&self) -> String {
+        format!("profiles.{}.steps", self.name)
+    }
+}
@@ -1,24 +1,33 @@
 use std::ffi::OsStr;
 use std::fs;
+use std::io::ErrorKind;
 use std::path::Path;
 
-use crate::error;
+use crate::build_state::STATE_DIR;
+use crate::error::{self, AutoTeXErr};
 
 const AUX_EXTENSIONS: [&str; 7] = ["aux", "log", "toc", "bbl", "blg", "lof", "out"];
 
 pub fn remove_aux(filepath: &Path) -> error::Result<()> {
-    let dir_entry = fs::read_dir(filepath)?;
+    let read_error = |err| AutoTeXErr::file(filepath, err);
+    let dir_entry = fs::read_dir(filepath).map_err(read_error)?;
 
     for entry in dir_entry {
-        let entry = entry?;
+        let entry = entry.map_err(read_error)?;
 
         if AUX_EXTENSIONS
             .iter()
             .any(|s| entry.path().extension().and_then(OsStr::to_str) == Some(s))
         {
-            fs::remove_file(entry.path())?;
+            let path = entry.path();
+            fs::remove_file(&path).map_err(|err| AutoTeXErr::file(&path, err))?;
         }
     }
 
-    Ok(())
+    let state_dir = filepath.join(STATE_DIR);
+    match fs::remove_dir_all(&state_dir) {
+        Ok(()) => Ok(()),
+        Err(err) if err.kind() == ErrorKind::NotFound => Ok(()),
+        Err(err) => Err(AutoTeXErr::file(&state_dir, err)),
+    }
 }
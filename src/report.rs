@@ -0,0 +1,214 @@
+use std::fmt::{self, Write as _};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::engines::BuildReport;
+use crate::error::{self, AutoTeXErr};
+use crate::tex_log::{Diagnostic, DiagnosticKind, Severity};
+use crate::texfile_info::TeXFileInfo;
+
+// If a machine readable report is written to stdout,
+// everything for humans goes to stderr instead
+static STDOUT_RESERVED: AtomicBool = AtomicBool::new(false);
+
+pub fn reserve_stdout() {
+    STDOUT_RESERVED.store(true, Ordering::Relaxed);
+}
+
+pub fn stdout_reserved() -> bool {
+    STDOUT_RESERVED.load(Ordering::Relaxed)
+}
+
+// Formats of a build report
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReportFormat {
+    Json,
+}
+
+impl ReportFormat {
+    pub fn from_name(name: &str) -> error::Result<Self> {
+        match name.to_lowercase().as_str() {
+            "json" => Ok(Self::Json),
+            _ => Err(AutoTeXErr::invalid_value("--report", name, "json")),
+        }
+    }
+}
+
+// A JSON value which is enough to write reports
+#[derive(Debug, Clone, PartialEq)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    Str(String),
+    Array(Vec<Json>),
+    Object(Vec<(&'static str, Json)>),
+}
+
+impl Json {
+    pub fn str<S: AsRef<str>>(s: S) -> Self {
+        Self::Str(s.as_ref().to_string())
+    }
+
+    pub fn opt<T, F: FnOnce(T) -> Json>(value: Option<T>, f: F) -> Self {
+        value.map_or(Json::Null, f)
+    }
+}
+
+fn write_escaped(f: &mut fmt::Formatter<'_>, s: &str) -> fmt::Result {
+    f.write_char('"')?;
+    for ch in s.chars() {
+        match ch {
+            '"' => f.write_str("\\\"")?,
+            '\\' => f.write_str("\\\\")?,
+            '\n' => f.write_str("\\n")?,
+            '\r' => f.write_str("\\r")?,
+            '\t' => f.write_str("\\t")?,
+            ch if (ch as u32) < 0x20 => write!(f, "\\u{:04x}", ch as u32)?,
+            ch => f.write_char(ch)?,
+        }
+    }
+    f.write_char('"')
+}
+
+impl fmt::Display for Json {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Json::Null => f.write_str("null"),
+            Json::Bool(b) => write!(f, "{}", b),
+            Json::Int(n) => write!(f, "{}", n),
+            Json::Float(x) if x.is_finite() => write!(f, "{}", x),
+            Json::Float(_) => f.write_str("null"),
+            Json::Str(s) => write_escaped(f, s),
+            Json::Array(items) => {
+                f.write_char('[')?;
+                for (idx, item) in items.iter().enumerate() {
+                    if idx > 0 {
+                        f.write_char(',')?;
+                    }
+                    item.fmt(f)?;
+                }
+                f.write_char(']')
+            }
+            Json::Object(fields) => {
+                f.write_char('{')?;
+                for (idx, (key, value)) in fields.iter().enumerate() {
+                    if idx > 0 {
+                        f.write_char(',')?;
+                    }
+                    write_escaped(f, key)?;
+                    f.write_char(':')?;
+                    value.fmt(f)?;
+                }
+                f.write_char('}')
+            }
+        }
+    }
+}
+
+pub fn severity_name(severity: Severity) -> &'static str {
+    match severity {
+        Severity::Error => "error",
+        Severity::Warning => "warning",
+    }
+}
+
+pub fn kind_name(kind: DiagnosticKind) -> &'static str {
+    match kind {
+        DiagnosticKind::Error => "error",
+        DiagnosticKind::UndefinedReference => "undefined_reference",
+        DiagnosticKind::UndefinedCitation => "undefined_citation",
+        DiagnosticKind::BadBox => "bad_box",
+        DiagnosticKind::MissingFile => "missing_file",
+        DiagnosticKind::Font => "font",
+        DiagnosticKind::Warning => "warning",
+    }
+}
+
+fn diagnostic_json(diag: &Diagnostic) -> Json {
+    Json::Object(vec![
+        ("severity", Json::str(severity_name(diag.severity))),
+        ("kind", Json::str(kind_name(diag.kind))),
+        ("file", Json::opt(diag.file.as_ref(), Json::str)),
+        ("line", Json::opt(diag.line, |line| Json::Int(line as i64))),
+        ("message", Json::str(&diag.message)),
+    ])
+}
+
+// Describe a build as a JSON document. `error` is what stopped the build
+// before it finished, like a missing tool.
+pub fn build_json(
+    engine: &str,
+    tex_info: &TeXFileInfo,
+    report: &BuildReport,
+    error: Option<&AutoTeXErr>,
+) -> Json {
+    let in_dir = |name: &str| Json::str(tex_info.current_dir.join(name).to_string_lossy());
+    let output = report
+        .output
+        .clone()
+        .unwrap_or_else(|| tex_info.get_main_pdf_file());
+    let output_exists = tex_info.in_dir(&output).exists();
+    let failure = report.failure();
+    let error = error.or(failure.as_ref());
+
+    let steps = report
+        .steps
+        .iter()
+        .map(|record| {
+            Json::Object(vec![
+                ("step", Json::str(&record.step)),
+                ("command", Json::str(&record.outcome.command_line)),
+                ("success", Json::Bool(record.outcome.success)),
+                (
+                    "exit_code",
+                    Json::opt(record.outcome.exit_code, |code| Json::Int(code.into())),
+                ),
+                (
+                    "duration",
+                    Json::Float(record.outcome.duration.as_secs_f64()),
+                ),
+            ])
+        })
+        .collect();
+
+    Json::Object(vec![
+        ("engine", Json::str(engine)),
+        ("main_file", in_dir(&tex_info.get_main_tex_file())),
+        ("success", Json::Bool(error.is_none())),
+        ("error", Json::opt(error, |err| Json::str(err.to_string()))),
+        (
+            "exit_code",
+            Json::opt(error, |err| Json::Int(err.exit_code().into())),
+        ),
+        ("passes", Json::Int(report.passes as i64)),
+        ("stable", Json::Bool(report.stable)),
+        ("duration", Json::Float(report.duration().as_secs_f64())),
+        ("steps", Json::Array(steps)),
+        (
+            "diagnostics",
+            Json::Array(report.diagnostics.iter().map(diagnostic_json).collect()),
+        ),
+        (
+            "output",
+            Json::Object(vec![
+                ("path", in_dir(&output)),
+                ("exists", Json::Bool(output_exists)),
+                ("pages", Json::opt(report.pages, |n| Json::Int(n as i64))),
+            ]),
+        ),
+    ])
+}
+
+// Write the report of a build to stdout in the given format
+pub fn emit(
+    format: ReportFormat,
+    engine: &str,
+    tex_info: &TeXFileInfo,
+    report: &BuildReport,
+    error: Option<&AutoTeXErr>,
+) {
+    match format {
+        ReportFormat::Json => println!("{}", build_json(engine, tex_info, report, error)),
+    }
+}
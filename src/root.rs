@@ -0,0 +1,211 @@
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+use crate::dependencies;
+use crate::error::{self, AutoTeXErr};
+use crate::magic_comments;
+use crate::tex_source;
+
+// How many directories above the input are searched for its root
+const MAX_PARENT_DIRS: usize = 3;
+
+// Files which mark the top of a project
+const PROJECT_MARKERS: [&str; 3] = [".git", "autotex.yaml", ".autotex"];
+
+// Join a relative path to a directory, removing `..` where it can
+fn join_path(dir: &Path, path: &Path) -> PathBuf {
+    let mut output = PathBuf::new();
+    for component in dir.join(path).components() {
+        match component {
+            Component::ParentDir
+                if matches!(output.components().next_back(), Some(Component::Normal(_))) =>
+            {
+                output.pop();
+            }
+            Component::CurDir => {}
+            component => output.push(component),
+        }
+    }
+    output
+}
+
+fn parent_dir(path: &Path) -> &Path {
+    path.parent().unwrap_or_else(|| Path::new(""))
+}
+
+fn read_source(path: &Path) -> error::Result<Option<String>> {
+    let source = tex_source::read_if_exists(path.with_extension("tex"))?;
+    Ok(source.map(|source| tex_source::strip_comments(&source)))
+}
+
+// Follow `% !TEX root = ../main.tex` from the given file to the file to compile
+fn follow_magic_root(filepath: &Path) -> error::Result<PathBuf> {
+    let mut path = filepath.to_path_buf();
+    let mut visited = vec![];
+    while !visited.contains(&path) {
+        let magic = magic_comments::read(path.with_extension("tex"))?;
+        let root = match magic.get("root") {
+            Some(root) => root,
+            None => break,
+        };
+        let root = join_path(parent_dir(&path), Path::new(root));
+        if root == path {
+            break;
+        }
+        if let Err(err) = fs::metadata(root.with_extension("tex")) {
+            return Err(AutoTeXErr::file(&root, err));
+        }
+        say!(
+            "Compile {}, the root of {} (% !TEX root)",
+            root.display(),
+            path.display()
+        );
+        visited.push(path);
+        path = root;
+    }
+    Ok(path)
+}
+
+// The main file of `\documentclass[../main]{subfiles}`
+fn subfiles_main(source: &str) -> Option<String> {
+    tex_source::find_commands(source, "documentclass")
+        .into_iter()
+        .find(|cmd| cmd.argument.trim() == "subfiles")
+        .and_then(|cmd| cmd.optional.first().map(|main| main.trim().to_string()))
+        .filter(|main| !main.is_empty())
+}
+
+// Whether the file can be compiled by itself
+fn is_root_document(source: &str) -> bool {
+    ["documentclass", "starttext", "bye"]
+        .iter()
+        .any(|name| tex_source::has_command(source, name))
+}
+
+// Take the tex files in the directory, without its subdirectories.
+// Entries which cannot be read are skipped.
+fn tex_files(dir: &Path) -> Vec<PathBuf> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return vec![],
+    };
+    let mut output: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.extension() == Some("tex".as_ref()) && path.is_file())
+        .collect();
+    output.sort();
+    output
+}
+
+// Whether the root reads the target through its graph of \input
+fn reads_file(root: &Path, target: &Path) -> error::Result<bool> {
+    let dir = parent_dir(root);
+    let mut queue = vec![root.to_path_buf()];
+    let mut visited = vec![];
+    while let Some(file) = queue.pop() {
+        let file = match fs::canonicalize(&file) {
+            Ok(file) => file,
+            Err(_) => continue,
+        };
+        if visited.contains(&file) {
+            continue;
+        }
+        if file == target {
+            return Ok(true);
+        }
+        // A file which cannot be read is reported by the engine if it matters
+        if let Ok(Some(source)) = tex_source::read_if_exists(&file) {
+            let source = tex_source::strip_comments(&source);
+            queue.extend(
+                dependencies::included_files(&source)
+                    .iter()
+                    .map(|path| dir.join(path)),
+            );
+        }
+        visited.push(file);
+    }
+    Ok(false)
+}
+
+// Search a root document which reads the file in the directory of the file and
+// in its parents, without their subdirectories. The search stops at the top of
+// a git repository or a project and at the home directory, and never reaches `/`.
+fn discover_root(filepath: &Path) -> error::Result<Option<PathBuf>> {
+    let target = match fs::canonicalize(filepath.with_extension("tex")) {
+        Ok(target) => target,
+        Err(_) => return Ok(None),
+    };
+    let home = dirs::home_dir().and_then(|home| fs::canonicalize(home).ok());
+    for dir in parent_dir(&target).ancestors().take(MAX_PARENT_DIRS + 1) {
+        if dir.parent().is_none() {
+            break;
+        }
+        for file in tex_files(dir) {
+            if file == target {
+                continue;
+            }
+            let is_root = read_source(&file).ok().flatten().is_some_and(|source| {
+                is_root_document(&source) && subfiles_main(&source).is_none()
+            });
+            if is_root && reads_file(&file, &target)? {
+                let current_dir = error::current_dir()?;
+                return Ok(Some(match file.strip_prefix(&current_dir) {
+                    Ok(relative) => relative.to_path_buf(),
+                    Err(_) => file,
+                }));
+            }
+        }
+        if PROJECT_MARKERS.iter().any(|name| dir.join(name).exists())
+            || Some(dir) == home.as_deref()
+        {
+            break;
+        }
+    }
+    Ok(None)
+}
+
+// Take the file to compile from the given one. It is the root of `% !TEX root`,
+// the main file of a subfile, or a root document which reads the file
+// if the file cannot be compiled by itself.
+pub fn find_root(filepath: &Path) -> error::Result<PathBuf> {
+    let path = follow_magic_root(filepath)?;
+    // A missing file is reported by the engine
+    let source = match read_source(&path)? {
+        Some(source) => source,
+        None => return Ok(path),
+    };
+
+    if let Some(main) = subfiles_main(&source) {
+        let main = join_path(parent_dir(&path), Path::new(&main));
+        if main.with_extension("tex").exists() {
+            say!(
+                "Compile {}, the main file of the subfile {}",
+                main.display(),
+                path.display()
+            );
+            return Ok(main);
+        }
+    }
+    if is_root_document(&source) {
+        return Ok(path);
+    }
+
+    match discover_root(&path)? {
+        Some(root) => {
+            say!(
+                "{} is not a root document, so compile {} which reads it",
+                path.display(),
+                root.display()
+            );
+            Ok(root)
+        }
+        None => {
+            say!(
+                "{} is not a root document, and no root document reads it",
+                path.display()
+            );
+            Ok(path)
+        }
+    }
+}
@@ -0,0 +1,523 @@
+use std::fs;
+use std::io::ErrorKind;
+use std::path::Path;
+
+use crate::error::{self, AutoTeXErr};
+use crate::tex_source;
+
+// TeX breaks every line of the log file at this column
+const MAX_PRINT_LINE: usize = 79;
+
+// Messages that LaTeX and its packages write into the log file
+// when the document must be compiled once more.
+// For instance,
+//   LaTeX Warning: Label(s) may have changed. Rerun to get cross-references right.
+//   Package rerunfilecheck Warning: File `main.out' has changed.
+//   (rerunfilecheck)                Rerun to get outlines right
+//   Package longtable Warning: Table widths have changed. Rerun LaTeX.
+//   Package hyperref Warning: Rerun to get /PageLabels entry.
+const RERUN_PATTERNS: [&str; 5] = [
+    "Rerun to get",
+    "Rerun LaTeX",
+    "rerun LaTeX",
+    "Please rerun",
+    "has changed. Rerun",
+];
+
+// Join the lines that TeX broke at MAX_PRINT_LINE columns
+pub fn unwrap_lines(log: &str) -> String {
+    let mut output = String::with_capacity(log.len());
+    for line in log.lines() {
+        output.push_str(line);
+        if line.chars().count() != MAX_PRINT_LINE {
+            output.push('\n');
+        }
+    }
+    output
+}
+
+// Read the log file and check whether the engine asked to run it again.
+// A missing log file does not ask anything.
+pub fn needs_rerun<P: AsRef<Path>>(logfile: P) -> error::Result<bool> {
+    let bytes = match fs::read(logfile.as_ref()) {
+        Ok(bytes) => bytes,
+        Err(err) if err.kind() == ErrorKind::NotFound => return Ok(false),
+        Err(err) => return Err(AutoTeXErr::file(logfile.as_ref(), err)),
+    };
+    let log = unwrap_lines(&String::from_utf8_lossy(&bytes));
+    Ok(log
+        .lines()
+        .any(|line| RERUN_PATTERNS.iter().any(|pat| line.contains(pat))))
+}
+
+// Errors and warnings are shown up to this number in the summary
+const MAX_SHOWN: usize = 10;
+
+// Lines after `! message` which are searched for `l.123`
+const ERROR_CONTEXT_LINES: usize = 12;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DiagnosticKind {
+    Error,
+    UndefinedReference,
+    UndefinedCitation,
+    BadBox,
+    MissingFile,
+    Font,
+    Warning,
+}
+
+// TeX shows where it stopped reading as two lines after an error:
+//   l.12 \section{Intro} \foo
+//                             bar baz
+// The first one has the source line read so far, and the second one the rest.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ErrorContext {
+    pub before: String,
+    pub after: String,
+}
+
+// An error or a warning found in the log file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub kind: DiagnosticKind,
+    pub file: Option<String>,
+    pub line: Option<usize>,
+    pub message: String,
+    pub context: Option<ErrorContext>,
+}
+
+impl Diagnostic {
+    fn new(severity: Severity, kind: DiagnosticKind, message: &str) -> Self {
+        Self {
+            severity,
+            kind,
+            file: None,
+            line: None,
+            message: message.trim().to_string(),
+            context: None,
+        }
+    }
+
+    // Where it happened, like `./main.tex:12`
+    pub fn location(&self) -> Option<String> {
+        match (&self.file, self.line) {
+            (Some(file), Some(line)) => Some(format!("{}:{}", file, line)),
+            (Some(file), None) => Some(file.clone()),
+            (None, Some(line)) => Some(format!("line {}", line)),
+            (None, None) => None,
+        }
+    }
+}
+
+// Files which TeX opened are written as `(./chapter.tex ... )` in the log.
+// Parentheses which do not open a file are kept as None to match `)`.
+#[derive(Debug, Default)]
+struct FileStack {
+    files: Vec<Option<String>>,
+}
+
+impl FileStack {
+    fn current(&self) -> Option<String> {
+        self.files.iter().rev().find_map(Clone::clone)
+    }
+
+    // Files in the log are relative to `dir`, the directory of the main file
+    fn update(&mut self, line: &str, dir: &Path) {
+        let mut rest = line;
+        while let Some(pos) = rest.find(['(', ')']) {
+            if rest[pos..].starts_with(')') {
+                self.files.pop();
+                rest = &rest[pos + 1..];
+                continue;
+            }
+            rest = &rest[pos + 1..];
+            let end = rest
+                .find(|ch: char| ch.is_whitespace() || ch == '(' || ch == ')')
+                .unwrap_or(rest.len());
+            let token = &rest[..end];
+            let is_file = !token.is_empty() && dir.join(token).is_file();
+            self.files.push(if is_file {
+                Some(token.to_string())
+            } else {
+                None
+            });
+            rest = &rest[end..];
+        }
+    }
+}
+
+// Read `on input line 12` or `at lines 10--12` in a warning
+fn line_in_message(message: &str) -> Option<usize> {
+    ["on input line ", "at lines ", "at line "]
+        .iter()
+        .find_map(|pat| message.split(pat).nth(1))
+        .and_then(|rest| {
+            let digits: String = rest.chars().take_while(char::is_ascii_digit).collect();
+            digits.parse().ok()
+        })
+}
+
+// Read `./main.tex:12: message` written with -file-line-error
+fn file_line_error(line: &str, dir: &Path) -> Option<(String, usize, String)> {
+    let mut search = 0;
+    while let Some(pos) = line[search..].find(':') {
+        let colon = search + pos;
+        let rest = &line[colon + 1..];
+        let digits: String = rest.chars().take_while(char::is_ascii_digit).collect();
+        if !digits.is_empty() && rest[digits.len()..].starts_with(": ") {
+            let file = &line[..colon];
+            if dir.join(file).is_file() {
+                let message = &rest[digits.len() + 2..];
+                return Some((file.to_string(), digits.parse().ok()?, message.to_string()));
+            }
+        }
+        search = colon + 1;
+    }
+    None
+}
+
+// Find `l.123 text` after an error, and read the line number and the context
+fn error_position(lines: &[&str]) -> Option<(usize, ErrorContext)> {
+    let (pos, rest) = lines
+        .iter()
+        .take(ERROR_CONTEXT_LINES)
+        .enumerate()
+        .find_map(|(pos, line)| Some((pos, line.strip_prefix("l.")?)))?;
+    let digits: String = rest.chars().take_while(char::is_ascii_digit).collect();
+    let line_num = digits.parse().ok()?;
+    let before = rest[digits.len()..]
+        .strip_prefix(' ')
+        .unwrap_or(&rest[digits.len()..]);
+    let after = lines.get(pos + 1).map_or("", |line| line.trim_start());
+    Some((
+        line_num,
+        ErrorContext {
+            before: before.to_string(),
+            after: after.to_string(),
+        },
+    ))
+}
+
+fn error_kind(message: &str) -> DiagnosticKind {
+    let lower = message.to_lowercase();
+    if (lower.contains("not found") && lower.contains("file")) || lower.contains("can't find file")
+    {
+        DiagnosticKind::MissingFile
+    } else if lower.contains("font") || lower.contains("metric (tfm)") {
+        DiagnosticKind::Font
+    } else {
+        DiagnosticKind::Error
+    }
+}
+
+fn warning_kind(message: &str) -> Option<DiagnosticKind> {
+    if message.starts_with("Overfull \\") || message.starts_with("Underfull \\") {
+        Some(DiagnosticKind::BadBox)
+    } else if message.contains("Reference `") && message.contains("undefined") {
+        Some(DiagnosticKind::UndefinedReference)
+    } else if message.contains("Citation `") && message.contains("undefined") {
+        Some(DiagnosticKind::UndefinedCitation)
+    } else if message.starts_with("No file ") {
+        Some(DiagnosticKind::MissingFile)
+    } else if message.starts_with("LaTeX Font Warning:") {
+        Some(DiagnosticKind::Font)
+    } else if message.starts_with("LaTeX Warning:")
+        || message.contains(" Warning: ")
+            && (message.starts_with("Package ") || message.starts_with("Class "))
+    {
+        if message.contains("File `") && message.contains("not found") {
+            Some(DiagnosticKind::MissingFile)
+        } else {
+            Some(DiagnosticKind::Warning)
+        }
+    } else {
+        None
+    }
+}
+
+// Parse the contents of a log file.
+// The files in the log are found relative to `dir`, the directory of the main file.
+pub fn parse_log(log: &str, dir: &Path) -> Vec<Diagnostic> {
+    let log = unwrap_lines(log);
+    let lines: Vec<&str> = log.lines().collect();
+    let mut stack = FileStack::default();
+    let mut output = vec![];
+
+    let mut idx = 0;
+    while idx < lines.len() {
+        let line = lines[idx];
+        idx += 1;
+
+        if let Some((file, line_num, message)) = file_line_error(line, dir) {
+            let mut diag = Diagnostic::new(Severity::Error, error_kind(&message), &message);
+            diag.file = Some(file);
+            diag.line = Some(line_num);
+            diag.context = error_position(&lines[idx..]).map(|(_, context)| context);
+            output.push(diag);
+            continue;
+        }
+
+        if let Some(message) = line.strip_prefix("! ") {
+            let mut diag = Diagnostic::new(Severity::Error, error_kind(message), message);
+            diag.file = stack.current();
+            if let Some((line_num, context)) = error_position(&lines[idx..]) {
+                diag.line = Some(line_num);
+                diag.context = Some(context);
+            }
+            output.push(diag);
+            continue;
+        }
+
+        if let Some(kind) = warning_kind(line) {
+            // Packages continue their warnings in lines starting with `(name)`
+            let mut message = line.to_string();
+            while idx < lines.len() && lines[idx].starts_with('(') && lines[idx].contains(")  ") {
+                let continued = lines[idx].split_once(')').map_or("", |(_, rest)| rest);
+                message.push(' ');
+                message.push_str(continued.trim());
+                idx += 1;
+            }
+            let mut diag = Diagnostic::new(Severity::Warning, kind, &message);
+            diag.file = stack.current();
+            diag.line = line_in_message(&message);
+            output.push(diag);
+            // Contents of a bad box are shown until an empty line,
+            // and their parentheses must not touch the file stack
+            if kind == DiagnosticKind::BadBox {
+                while idx < lines.len() && !lines[idx].trim().is_empty() {
+                    idx += 1;
+                }
+                continue;
+            }
+        }
+
+        stack.update(line, dir);
+    }
+
+    output
+}
+
+// Read `Output written on main.pdf (12 pages, 34567 bytes).`
+pub fn output_info(log: &str) -> Option<(String, Option<usize>)> {
+    let log = unwrap_lines(log);
+    let rest = log
+        .lines()
+        .find_map(|line| line.strip_prefix("Output written on "))?;
+    let (file, rest) = rest.split_once(" (")?;
+    let pages = rest
+        .split_whitespace()
+        .next()
+        .and_then(|num| num.parse().ok());
+    Some((file.to_string(), pages))
+}
+
+// Lines of the source file shown before and after the line of an error
+const SOURCE_CONTEXT_LINES: usize = 2;
+
+// Column of the source line where TeX stopped reading.
+// TeX may shorten the text read so far as `...text`, so it is searched in the line.
+fn error_column(source_line: &str, context: &ErrorContext) -> Option<usize> {
+    let before = context
+        .before
+        .strip_prefix("...")
+        .unwrap_or(&context.before);
+    if before.is_empty() {
+        return Some(0);
+    }
+    let trimmed = source_line.trim_end();
+    if trimmed.ends_with(before) && context.after.is_empty() {
+        return Some(trimmed.len());
+    }
+    let pos = source_line.find(before)?;
+    Some(pos + before.len())
+}
+
+// Print the lines around the line of an error, and mark where TeX stopped reading.
+// The file is read relative to `dir`, the directory of the main file.
+fn print_source_context(diag: &Diagnostic, dir: &Path) {
+    let (file, line_num) = match (&diag.file, diag.line) {
+        (Some(file), Some(line_num)) if line_num > 0 => (file, line_num),
+        _ => return,
+    };
+    let source = match tex_source::read_if_exists(dir.join(file)) {
+        Ok(Some(source)) => source,
+        _ => return,
+    };
+    let lines: Vec<&str> = source.lines().collect();
+    if line_num > lines.len() {
+        return;
+    }
+
+    let column = diag
+        .context
+        .as_ref()
+        .and_then(|context| error_column(lines[line_num - 1], context));
+    let first = line_num.saturating_sub(SOURCE_CONTEXT_LINES).max(1);
+    let last = (line_num + SOURCE_CONTEXT_LINES).min(lines.len());
+    let width = last.to_string().len();
+    for num in first..=last {
+        let marker = if num == line_num { '>' } else { ' ' };
+        say!(
+            "    {} {:>width$} | {}",
+            marker,
+            num,
+            lines[num - 1],
+            width = width
+        );
+        if let Some(column) = column.filter(|_| num == line_num) {
+            // Keep tabs so that the caret lines up with the source line
+            let padding: String = lines[num - 1][..column]
+                .chars()
+                .map(|ch| if ch == '\t' { '\t' } else { ' ' })
+                .collect();
+            say!("      {:>width$} | {}^", "", padding, width = width);
+        }
+    }
+}
+
+fn print_list(title: &str, diags: &[&Diagnostic], source_dir: Option<&Path>) {
+    if diags.is_empty() {
+        return;
+    }
+    say!("{} ({}):", title, diags.len());
+    for diag in diags.iter().take(MAX_SHOWN) {
+        match diag.location() {
+            Some(location) => say!("  {}: {}", location, diag.message),
+            None => say!("  {}", diag.message),
+        }
+        if let Some(dir) = source_dir {
+            print_source_context(diag, dir);
+        }
+    }
+    if diags.len() > MAX_SHOWN {
+        say!("  ... and {} more", diags.len() - MAX_SHOWN);
+    }
+}
+
+// Print errors and warnings of a build concisely.
+// The source of errors is read relative to `dir`, the directory of the main file.
+pub fn print_summary(diags: &[Diagnostic], dir: &Path) {
+    let of_kind = |kinds: &[DiagnosticKind], severity: Severity| -> Vec<&Diagnostic> {
+        diags
+            .iter()
+            .filter(|diag| diag.severity == severity && kinds.contains(&diag.kind))
+            .collect()
+    };
+    use DiagnosticKind::*;
+
+    print_list(
+        "Errors",
+        &of_kind(&[Error, MissingFile, Font], Severity::Error),
+        Some(dir),
+    );
+    print_list(
+        "Undefined references and citations",
+        &of_kind(&[UndefinedReference, UndefinedCitation], Severity::Warning),
+        None,
+    );
+    print_list(
+        "Missing files",
+        &of_kind(&[MissingFile], Severity::Warning),
+        None,
+    );
+    print_list("Font warnings", &of_kind(&[Font], Severity::Warning), None);
+    print_list(
+        "Other warnings",
+        &of_kind(&[Warning], Severity::Warning),
+        None,
+    );
+
+    let bad_boxes = of_kind(&[BadBox], Severity::Warning).len();
+    if bad_boxes > 0 {
+        say!("Overfull and underfull boxes: {}", bad_boxes);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    // A directory with the given empty files, since only existing files
+    // are taken as files in the log
+    fn dir_with(name: &str, files: &[&str]) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("autotex-log-{}-{}", std::process::id(), name));
+        for file in files {
+            let path = dir.join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, "").unwrap();
+        }
+        dir
+    }
+
+    #[test]
+    fn unwrap_joins_lines_of_max_print_line() {
+        let first = "x".repeat(MAX_PRINT_LINE);
+        let log = format!("{}\nyz\nshort\n", first);
+        assert_eq!(unwrap_lines(&log), format!("{}yz\nshort\n", first));
+    }
+
+    #[test]
+    fn file_wrapped_at_max_print_line() {
+        let file = "./chapters/a-chapter-with-a-rather-long-name-for-the-log.tex";
+        let dir = dir_with("wrapped", &["main.tex", "intro.tex", file]);
+        // TeX breaks the file name in the middle to keep 79 columns
+        let opened = format!("(./main.tex (./intro.tex) ({}", file);
+        let (first, rest) = opened.split_at(MAX_PRINT_LINE);
+        let log = format!(
+            "{}\n{}\n! Undefined control sequence.\nl.7 \\foo\n\n",
+            first, rest
+        );
+
+        let diags = parse_log(&log, &dir);
+        assert_eq!(diags.len(), 1);
+        assert_eq!(diags[0].file.as_deref(), Some(file));
+        assert_eq!(diags[0].line, Some(7));
+        assert_eq!(diags[0].message, "Undefined control sequence.");
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn closed_files_and_other_parentheses() {
+        let dir = dir_with("stack", &["main.tex", "chap.tex"]);
+        let log = "(./main.tex (./chap.tex (see the transcript file)\n\
+                   LaTeX Warning: Reference `fig' on page 1 undefined on input line 3.\n\
+                   ) [1]\n\
+                   LaTeX Warning: Citation `knuth' on page 1 undefined on input line 9.\n\
+                   )\n";
+
+        let diags = parse_log(log, &dir);
+        assert_eq!(diags.len(), 2);
+        assert_eq!(diags[0].kind, DiagnosticKind::UndefinedReference);
+        assert_eq!(diags[0].file.as_deref(), Some("./chap.tex"));
+        assert_eq!(diags[0].line, Some(3));
+        assert_eq!(diags[1].kind, DiagnosticKind::UndefinedCitation);
+        assert_eq!(diags[1].file.as_deref(), Some("./main.tex"));
+        assert_eq!(diags[1].line, Some(9));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn file_line_error_with_context() {
+        let dir = dir_with("file-line", &["main.tex"]);
+        let log =
+            "./main.tex:12: Undefined control sequence.\nl.12 \\section{Intro} \\foo\n      bar\n";
+
+        let diags = parse_log(log, &dir);
+        assert_eq!(diags.len(), 1);
+        assert_eq!(diags[0].file.as_deref(), Some("./main.tex"));
+        assert_eq!(diags[0].line, Some(12));
+        let context = diags[0].context.as_ref().unwrap();
+        assert_eq!(context.before, "\\section{Intro} \\foo");
+        assert_eq!(context.after, "bar");
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
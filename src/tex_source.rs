@@ -0,0 +1,157 @@
+use std::fs;
+use std::io::ErrorKind;
+use std::path::Path;
+
+use crate::error::{self, AutoTeXErr};
+
+// A command found in TeX source, like \usepackage[opt]{name}
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TeXCommand {
+    pub optional: Vec<String>,
+    pub argument: String,
+}
+
+// Remove TeX comments from the source, keeping escaped percent signs
+pub fn strip_comments(source: &str) -> String {
+    let mut output = String::with_capacity(source.len());
+    for line in source.lines() {
+        let mut escaped = false;
+        for ch in line.chars() {
+            if ch == '%' && !escaped {
+                break;
+            }
+            escaped = ch == '\\' && !escaped;
+            output.push(ch);
+        }
+        output.push('\n');
+    }
+    output
+}
+
+// Read a group which starts at the beginning of `source` and ends with `close`.
+// Return the contents of the group and the rest of the source.
+fn read_group(source: &str, open: char, close: char) -> Option<(&str, &str)> {
+    let mut depth = 0usize;
+    for (idx, ch) in source.char_indices() {
+        if ch == open {
+            depth += 1;
+        } else if ch == close {
+            depth = depth.checked_sub(1)?;
+            if depth == 0 {
+                return Some((&source[open.len_utf8()..idx], &source[idx + 1..]));
+            }
+        }
+    }
+    None
+}
+
+// Find every `\name` in the source and read its arguments.
+// Optional arguments in brackets are collected in order, and the mandatory
+// argument is either a braced group or a bare word like `\input chapter`.
+// Commands without any argument are skipped.
+pub fn find_commands(source: &str, name: &str) -> Vec<TeXCommand> {
+    let pattern = format!("\\{}", name);
+    let mut output = vec![];
+    let mut rest = source;
+
+    while let Some(pos) = rest.find(&pattern) {
+        rest = &rest[pos + pattern.len()..];
+        // \input must not match \inputencoding
+        if rest.starts_with(|ch: char| ch.is_ascii_alphabetic() || ch == '@') {
+            continue;
+        }
+
+        let mut optional = vec![];
+        let mut args = rest.trim_start();
+        while args.starts_with('[') {
+            match read_group(args, '[', ']') {
+                Some((opt, remain)) => {
+                    optional.push(opt.trim().to_string());
+                    args = remain.trim_start();
+                }
+                None => break,
+            }
+        }
+
+        let argument = if args.starts_with('{') {
+            match read_group(args, '{', '}') {
+                Some((arg, remain)) => {
+                    rest = remain;
+                    arg.trim().to_string()
+                }
+                None => continue,
+            }
+        } else {
+            let end = args
+                .find(|ch: char| ch.is_whitespace() || ch == '\\' || ch == '}')
+                .unwrap_or(args.len());
+            rest = &args[end..];
+            args[..end].to_string()
+        };
+
+        // Some commands like \makeindex[name=foo] have only optional arguments
+        if !argument.is_empty() || !optional.is_empty() {
+            output.push(TeXCommand { optional, argument });
+        }
+    }
+
+    output
+}
+
+// Whether `\name` is used in the source, with or without arguments
+pub fn has_command(source: &str, name: &str) -> bool {
+    let pattern = format!("\\{}", name);
+    source.match_indices(&pattern).any(|(pos, _)| {
+        !source[pos + pattern.len()..].starts_with(|ch: char| ch.is_ascii_alphabetic() || ch == '@')
+    })
+}
+
+// Split a comma separated argument like `\usepackage{amsmath, amssymb}`
+pub fn split_list(argument: &str) -> impl Iterator<Item = &str> {
+    argument
+        .split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+}
+
+// Take the value of `key` in options like `name=foo, options={-s foo.ist}`
+pub fn option_value(options: &str, key: &str) -> Option<String> {
+    let mut items = vec![];
+    let mut depth = 0usize;
+    let mut start = 0;
+    for (idx, ch) in options.char_indices() {
+        match ch {
+            '{' => depth += 1,
+            '}' => depth = depth.saturating_sub(1),
+            ',' if depth == 0 => {
+                items.push(&options[start..idx]);
+                start = idx + 1;
+            }
+            _ => {}
+        }
+    }
+    items.push(&options[start..]);
+
+    items
+        .into_iter()
+        .filter_map(|item| item.split_once('='))
+        .find(|(name, _)| name.trim() == key)
+        .map(|(_, value)| {
+            let value = value.trim();
+            value
+                .strip_prefix('{')
+                .and_then(|value| value.strip_suffix('}'))
+                .unwrap_or(value)
+                .trim()
+                .to_string()
+        })
+}
+
+// Read a file written by TeX or its tools. A missing file is not an error.
+pub fn read_if_exists<P: AsRef<Path>>(path: P) -> error::Result<Option<String>> {
+    match fs::read(path.as_ref()) {
+        Ok(bytes) => Ok(Some(String::from_utf8_lossy(&bytes).into_owned())),
+        Err(err) if err.kind() == ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(AutoTeXErr::file(path.as_ref(), err)),
+    }
+}
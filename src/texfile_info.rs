@@ -1,4 +1,4 @@
-use std::ffi::{OsStr, OsString};
+use std::ffi::OsString;
 use std::fs;
 use std::path::{Path, PathBuf};
 use std::time::SystemTime;
@@ -9,77 +9,114 @@ use std::process::Command;
 #[cfg(windows)]
 use powershell_script as ps;
 
-use yaml_rust::YamlLoader;
-
+use crate::dependencies;
 use crate::error::{self, AutoTeXErr};
+use crate::magic_comments::{self, MagicComments};
+use crate::root;
+use crate::tex_source::{self, TeXCommand};
+#[cfg(not(windows))]
+use crate::tools;
 
 // A container of files info
 #[derive(Debug)]
 pub struct TeXFileInfo {
     pub filenames: Vec<PathBuf>,
     pub mainfile: OsString,
+    // Name of the output files like the pdf and the log file.
+    // It is the name of the main file unless a profile renames the output.
+    pub jobname: OsString,
     pub current_dir: PathBuf,
-    pub bibtex_exists: bool,
-    pub mkindex_exists: bool,
+    pub magic_comments: MagicComments,
     pub asymptote_exists: bool,
 }
 
-// TeX relative extensions
-const TEX_FILES_EXTENSIONS: [&str; 4] = ["tex", "bib", "idx", "toc"];
-
 // Implementation of TeXFileInfo
 impl TeXFileInfo {
     fn new() -> Self {
         Self {
             filenames: vec![],
             mainfile: OsString::new(),
+            jobname: OsString::new(),
             current_dir: Path::new("").to_path_buf(),
-            bibtex_exists: false,
-            mkindex_exists: false,
+            magic_comments: MagicComments::default(),
             asymptote_exists: false,
         }
     }
 
-    pub fn take_time(&self) -> error::Result<Vec<SystemTime>> {
-        let mut output: Vec<SystemTime> = vec![];
-        let path_lst = self.filenames.clone();
-        for path in path_lst {
-            output.push(path.metadata()?.modified()?);
-        }
-        Ok(output)
+    // Path of a file of the job, like an aux file, in the directory of the main file
+    pub fn in_dir<P: AsRef<Path>>(&self, name: P) -> PathBuf {
+        self.current_dir.join(name)
+    }
+
+    // A missing file has no time, so that removing or creating it is a change
+    pub fn take_time(&self) -> Vec<Option<SystemTime>> {
+        self.filenames
+            .iter()
+            .map(|path| path.metadata().and_then(|meta| meta.modified()).ok())
+            .collect()
     }
 
     #[cfg(not(windows))]
-    pub fn show_pdf(&self) -> error::Result<()> {
-        let pdf_engine = get_pdf_viewer()?;
-        let mut pdf_name = self.mainfile.clone();
+    pub fn show_pdf(&self, pdf_engine: &str) -> error::Result<()> {
+        let mut pdf_name = self.jobname.clone();
         pdf_name.push(".pdf");
 
-        Command::new(pdf_engine).arg(pdf_name).spawn()?;
+        if tools::find_program(pdf_engine).is_none() {
+            return Err(AutoTeXErr::MissingToolErr {
+                program: pdf_engine.to_string(),
+                step: Some("view".to_string()),
+                key: "pdf".to_string(),
+            });
+        }
+        Command::new(pdf_engine)
+            .arg(self.in_dir(pdf_name))
+            .spawn()
+            .map_err(|err| AutoTeXErr::file(Path::new(pdf_engine), err))?;
         Ok(())
     }
 
     #[cfg(windows)]
-    pub fn show_pdf(&self) -> error::Result<()> {
-        let pdf_engine = get_pdf_viewer()?;
-        let mut pdf_name = self.mainfile.clone();
+    pub fn show_pdf(&self, pdf_engine: &str) -> error::Result<()> {
+        let mut pdf_name = self.jobname.clone();
         pdf_name.push(".pdf");
 
         let ps_cmd = format!(
             "{} {}",
-            pdf_engine.display(),
-            pdf_name
+            pdf_engine,
+            self.in_dir(pdf_name)
+                .into_os_string()
                 .into_string()
                 .expect("cannot convert this into `String`")
         );
 
         if ps::run(&ps_cmd).is_err() {
-            Err(AutoTeXErr::CannotShowPdfErr)
+            Err(AutoTeXErr::CannotShowPdfErr(pdf_engine.to_string()))
         } else {
             Ok(())
         }
     }
 
+    // Take the asy files which the asymptote package generated in the last pass.
+    // Those are named like `jobname-1.asy`.
+    pub fn generated_asy_files(&self) -> error::Result<Vec<PathBuf>> {
+        let prefix = [self.jobname.to_str().unwrap_or_default(), "-"].concat();
+        let mut output = vec![];
+        let read_error = |err| AutoTeXErr::file(&self.current_dir, err);
+        for entry in fs::read_dir(&self.current_dir).map_err(read_error)? {
+            let path = PathBuf::from(entry.map_err(read_error)?.file_name());
+            let number = path
+                .to_str()
+                .and_then(|name| name.strip_prefix(&prefix))
+                .and_then(|name| name.strip_suffix(".asy"))
+                .and_then(|num| num.parse::<usize>().ok());
+            if let Some(number) = number {
+                output.push((number, path));
+            }
+        }
+        output.sort();
+        Ok(output.into_iter().map(|(_, path)| path).collect())
+    }
+
     pub fn get_main_tex_file(&self) -> String {
         [
             self.mainfile
@@ -92,24 +129,37 @@ impl TeXFileInfo {
 
     pub fn get_main_pdf_file(&self) -> String {
         [
-            self.mainfile
+            self.jobname
                 .to_str()
                 .expect("Cannot take a filename to compile"),
             ".pdf",
         ]
         .concat()
     }
+
+    pub fn get_main_log_file(&self) -> String {
+        [
+            self.jobname
+                .to_str()
+                .expect("Cannot take a filename to compile"),
+            ".log",
+        ]
+        .concat()
+    }
 }
 
-// Take all tex related files in the current directory
+// Take the main file and the files which it reads.
+// A file which is not a root document compiles its root instead.
 pub fn get_files_info(filepath: &Path) -> error::Result<TeXFileInfo> {
     let mut output = TeXFileInfo::new();
+    let filepath = &root::find_root(filepath)?;
 
     output.mainfile = if let Some(file) = filepath.file_stem() {
         file.to_os_string()
     } else {
-        return Err(AutoTeXErr::NoFilenameInputErr);
+        return Err(AutoTeXErr::NoFilenameInputErr(filepath.to_path_buf()));
     };
+    output.jobname = output.mainfile.clone();
 
     let file_dir = filepath.ancestors().nth(1);
     output.current_dir = if file_dir == Some(Path::new("")) {
@@ -117,70 +167,39 @@ pub fn get_files_info(filepath: &Path) -> error::Result<TeXFileInfo> {
     } else if file_dir.is_some() {
         file_dir.unwrap().to_path_buf()
     } else {
-        return Err(AutoTeXErr::NoFilenameInputErr);
+        return Err(AutoTeXErr::NoFilenameInputErr(filepath.to_path_buf()));
     };
 
-    for path in walkdir::WalkDir::new(&output.current_dir) {
-        match path {
-            Ok(dir) => {
-                // Filter out directories and files not related with TeX
-                let file_ext = dir.path().extension();
-                // Detect whether this is a file
-                // If this is a directory, then continue the loop
-
-                // FIXME: Because of this part, if there are several tex files and
-                // if the only one of which requires "bib" file, then the others
-                // should have "bib" file, otherwise it does not compile continuously
-                // properly.
-                if let Some(ext) = file_ext {
-                    if TEX_FILES_EXTENSIONS.iter().any(|x| OsStr::new(x) == ext) {
-                        if !output.bibtex_exists {
-                            output.bibtex_exists = ext == "bib";
-                        }
-                        if !output.mkindex_exists {
-                            output.mkindex_exists = ext == "idx";
-                        }
-                        output.filenames.push(dir.into_path());
-                    }
-                }
-            }
-            Err(_) => return Err(AutoTeXErr::TakeFilesErr),
-        }
-    }
-    output.filenames.sort();
+    output.filenames =
+        dependencies::watch_files(&output.current_dir, Path::new(&output.get_main_tex_file()))?;
+    output.asymptote_exists = uses_asymptote(&output.filenames)?;
+    output.magic_comments = magic_comments::read(filepath.with_extension("tex"))?;
     Ok(output)
 }
 
-// Get a pdf viewer from a config file
-// The config file must in at .config/autotex directory
-// and its name is config.yaml
-#[cfg(target_os = "linux")]
-const DEFAULT_PDF_VIEW: &str = "xdg-open";
-
-#[cfg(target_os = "macos")]
-const DEFAULT_PDF_VIEW: &str = "open";
-
-#[cfg(target_os = "windows")]
-const DEFAULT_PDF_VIEW: &str = "ii";
-
-fn get_pdf_viewer() -> error::Result<PathBuf> {
-    let mut config_dir = if dirs::config_dir().is_none() {
-        return Err(AutoTeXErr::NoneError);
-    } else {
-        dirs::config_dir().unwrap()
+// Detect \usepackage{asymptote} (or \RequirePackage in a package) in LaTeX
+// or \input asymptote in plain TeX, in the main file or in a file which it reads
+fn uses_asymptote(files: &[PathBuf]) -> error::Result<bool> {
+    let in_list =
+        |cmd: &TeXCommand| tex_source::split_list(&cmd.argument).any(|x| x == "asymptote");
+    let is_source = |file: &&PathBuf| {
+        matches!(
+            file.extension().and_then(|ext| ext.to_str()),
+            Some("tex" | "sty" | "cls")
+        )
     };
-    config_dir.push("autotex/config.yaml");
-    let contents = fs::read_to_string(config_dir).unwrap_or_default();
-    let docs = YamlLoader::load_from_str(&contents)?;
-    let doc = docs.get(0);
-    if let Some(d) = doc {
-        if d["pdf"].is_badvalue() {
-            Ok(Path::new(DEFAULT_PDF_VIEW).to_path_buf())
-        } else {
-            let pdf_view = d["pdf"].as_str().unwrap();
-            Ok(Path::new(pdf_view).to_path_buf())
+    for file in files.iter().filter(is_source) {
+        let source = match tex_source::read_if_exists(file)? {
+            Some(source) => tex_source::strip_comments(&source),
+            None => continue,
+        };
+        let found = ["usepackage", "RequirePackage", "input"]
+            .iter()
+            .flat_map(|name| tex_source::find_commands(&source, name))
+            .any(|cmd| in_list(&cmd));
+        if found {
+            return Ok(true);
         }
-    } else {
-        Ok(Path::new(DEFAULT_PDF_VIEW).to_path_buf())
     }
+    Ok(false)
 }
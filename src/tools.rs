@@ -0,0 +1,106 @@
+use std::collections::HashMap;
+use std::env;
+use std::path::{Path, PathBuf};
+
+use crate::config::{Node, Value};
+use crate::error::{self, AutoTeXErr};
+
+// Extensions of executables which are tried on windows
+#[cfg(windows)]
+const EXECUTABLE_EXTENSIONS: [&str; 4] = ["", ".exe", ".bat", ".cmd"];
+
+#[cfg(not(windows))]
+const EXECUTABLE_EXTENSIONS: [&str; 1] = [""];
+
+#[cfg(unix)]
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    path.metadata()
+        .is_ok_and(|meta| meta.is_file() && meta.permissions().mode() & 0o111 != 0)
+}
+
+#[cfg(not(unix))]
+fn is_executable(path: &Path) -> bool {
+    path.is_file()
+}
+
+// Find a program as the shell does. A program with a directory part
+// is taken as it is, and the others are searched in PATH.
+pub fn find_program(program: &str) -> Option<PathBuf> {
+    let path = Path::new(program);
+    let candidates: Vec<PathBuf> = if path.components().count() > 1 {
+        vec![path.to_path_buf()]
+    } else {
+        let paths = env::var_os("PATH")?;
+        env::split_paths(&paths)
+            .map(|dir| dir.join(program))
+            .collect()
+    };
+    candidates.iter().find_map(|candidate| {
+        EXECUTABLE_EXTENSIONS.iter().find_map(|ext| {
+            let mut name = candidate.clone().into_os_string();
+            name.push(ext);
+            let name = PathBuf::from(name);
+            is_executable(&name).then_some(name)
+        })
+    })
+}
+
+// Paths of programs given by the `tools` part of the config file, like
+//   tools:
+//     bibtex: /usr/local/texlive/2024/bin/x86_64-linux/bibtex
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ToolPaths {
+    paths: HashMap<String, String>,
+}
+
+impl ToolPaths {
+    // Take the paths of the config over the ones which are already set
+    pub fn merge(&mut self, node: &Node) -> error::Result<()> {
+        if !matches!(node.value, Value::Map(_)) {
+            return Err(node.expected("a map from programs to their paths"));
+        }
+        for (name, path) in node.entries() {
+            self.paths.insert(name.to_string(), path.as_string()?);
+        }
+        Ok(())
+    }
+
+    // Configured programs and their paths, sorted by name
+    pub fn configured(&self) -> Vec<(&str, &str)> {
+        let mut output: Vec<_> = self
+            .paths
+            .iter()
+            .map(|(name, path)| (name.as_str(), path.as_str()))
+            .collect();
+        output.sort();
+        output
+    }
+
+    // The configured path of the program, or its name to be searched in PATH
+    pub fn get<'a>(&'a self, program: &'a str) -> &'a str {
+        self.paths.get(program).map_or(program, String::as_str)
+    }
+
+    // Find the program which the step needs, and fail with a message
+    // which tells how to fix it if it cannot be found.
+    pub fn require(&self, program: &str, step: &str) -> error::Result<String> {
+        let path = self.get(program);
+        match find_program(path) {
+            // Programs run in the directory of the main file, so a path like
+            // `bin/tool` is kept relative to where autotex started
+            Some(found) if found.is_relative() && found.components().count() > 1 => {
+                Ok(error::current_dir()?
+                    .join(path)
+                    .to_string_lossy()
+                    .into_owned())
+            }
+            Some(_) => Ok(path.to_string()),
+            None => Err(AutoTeXErr::MissingToolErr {
+                program: path.to_string(),
+                step: Some(step.to_string()),
+                key: format!("tools.{}", program),
+            }),
+        }
+    }
+}